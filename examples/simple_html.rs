@@ -1,5 +1,3 @@
-use writedown;
-
 pub mod html {
     use writedown::{ast, Render};
 
@@ -1,12 +1,51 @@
+//! The document tree built by the parser.
+//!
+//! # JSON
+//!
+//! With the `serde` feature, the types here and in `token` implement
+//! `Serialize` and `Deserialize`, so that tools in other languages can
+//! read parsed documents, e.g. through `serde_json`. The representation is
+//! kept stable:
+//!
+//! - Structs are objects keyed by field name; an absent `Option` is `null`.
+//! - Enums with data (`Node`, `ParagraphChild`, `Block`, `token::Literal`
+//!   and `token::TokenKind`) are `{"type": ..., "data": ...}` with the
+//!   variant name in snake_case. Variants without data have no `"data"`,
+//!   e.g. `{"type": "soft_break"}`.
+//! - `Align` and `link::Link` are lowercase strings, e.g. `"right"`.
+//! - `token::Span` is `{"start": ..., "end": ...}` in bytes of the UTF-8
+//!   source, the end excluded. Functions, links, jumps, figures and
+//!   comments have one.
+//! - `token::Date` is `{"year": 2021, "month": 4, "day": 1}`.
+//!
+//! For example, `see @<jmp>(intro)` is the paragraph
+//!
+//! ```json
+//! {"type": "paragraph", "data": {"tag": null, "child": [
+//!   {"type": "sentence", "data": "see "},
+//!   {"type": "jump", "data": {"tag": "intro", "span": {"start": 4, "end": 17}}}
+//! ]}}
+//! ```
+
+use crate::link;
 use crate::token;
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "type", content = "data", rename_all = "snake_case")
+)]
 pub enum Node {
     //Top(Option<Header>, Rc<Node>),
+    Header(Header),
     Section(Section),
     Paragraph(Paragraph),
+    Figure(Figure),
+    Table(Table),
+    Comment(Comment),
     Func,
-    List,
+    List(List),
     Block(Block),
     Math,
     InlineCode,
@@ -16,50 +55,558 @@ pub enum Node {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Section {
     pub level: usize,
     pub title: String,
+    pub tag: Option<String>,
     pub child: Vec<Node>,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Paragraph {
+    pub tag: Option<String>,
     pub child: Vec<ParagraphChild>,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "type", content = "data", rename_all = "snake_case")
+)]
 pub enum ParagraphChild {
     Sentence(String),
+    /// A line break within a paragraph, see `linebreak::join`.
+    SoftBreak,
     Func(Func),
+    Tag(String),
+    Jump(Jump),
+    Footnote(Footnote),
+    Mention(Mention),
+    Link(Link),
+    LinkDef(LinkDef),
+    LinkRef(LinkRef),
+    Ruby(Ruby),
+    Math(Math),
+    /// Inline code, `` `x` ``, without the backquotes.
+    Code(String),
+}
+
+/// `$inline$` or `$$display$$` TeX math, passed through as is.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Math {
+    /// The TeX source without the dollars.
+    pub src: String,
+    pub display: bool,
+}
+
+impl Math {
+    /// Strip the dollars of a `TokenKind::Math` token.
+    pub fn new(s: &str) -> Self {
+        match s.strip_prefix("$$").and_then(|s| s.strip_suffix("$$")) {
+            Some(src) => Self {
+                src: src.trim().to_string(),
+                display: true,
+            },
+            None => Self {
+                src: s[1..s.len() - 1].to_string(),
+                display: false,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Math {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.display {
+            write!(f, "$${}$$", self.src)
+        } else {
+            write!(f, "${}$", self.src)
+        }
+    }
+}
+
+/// `- item`, `- [x] task` or `1. item` lines; a more indented item starts a
+/// nested list.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct List {
+    /// The first number of an ordered list, `None` for bullets.
+    pub start: Option<usize>,
+    pub items: Vec<ListItem>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ListItem {
+    /// `Some(done)` for a task list item, `- [ ]` or `- [x]`.
+    pub check: Option<bool>,
+    pub child: Vec<ParagraphChild>,
+    pub list: Option<List>,
+}
+
+/// `# comment` or `#[ comment ]#`, kept when parsing with
+/// `parse::Options::keep_comments`.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Comment {
+    /// The text without the markers.
+    pub body: String,
+    pub block: bool,
+    pub span: token::Span,
+}
+
+impl Comment {
+    pub fn new(s: &str, span: token::Span) -> Self {
+        let (body, block) = match s.strip_prefix("#[") {
+            Some(b) => (b.strip_suffix("]#").unwrap_or(b).trim(), true),
+            None => (s[1..].trim(), false),
+        };
+        Self {
+            body: body.to_string(),
+            block,
+            span,
+        }
+    }
+}
+
+/// `@<img>(path, alt=..., width=...){caption}` on its own.
+///
+/// A float `width` is a fraction of the text width, an integer is in
+/// pixels and a string is passed through (e.g. `"5cm"`).
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Figure {
+    pub path: String,
+    pub alt: Option<String>,
+    pub width: Option<token::Literal>,
+    pub caption: Option<String>,
+    pub tag: Option<String>,
+    pub span: token::Span,
+}
+
+/// A table of `| a | b |` rows, or `@<table>(caption){rows}`.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Table {
+    pub caption: Option<String>,
+    pub tag: Option<String>,
+    /// Per column; may be shorter than the rows.
+    pub align: Vec<Align>,
+    pub header: Vec<Vec<Cell>>,
+    pub body: Vec<Vec<Cell>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Align {
+    Default,
+    Left,
+    Center,
+    Right,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cell {
+    pub child: Vec<ParagraphChild>,
+}
+
+/// `@<jmp>(tag)`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Jump {
+    pub tag: String,
+    pub span: token::Span,
+}
+
+/// `@<ft>{body}` marks a footnote in place, while `@<ftref>(tag){body}`
+/// defines the footnote for the `@[tag]` written elsewhere.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Footnote {
+    pub tag: Option<String>,
+    pub body: String,
+}
+
+/// A bare URL or email address, or `@<link>(url, title=...){label}`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Link {
+    pub kind: link::Link,
+    pub url: String,
+    pub label: Option<String>,
+    pub title: Option<String>,
+    pub span: token::Span,
+}
+
+/// `@<linkdef>(name, url, title=...)`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LinkDef {
+    pub name: String,
+    pub link: Link,
+}
+
+/// `@<linkref>(name){label}`, replaced by a `Link` in `link::resolve`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LinkRef {
+    pub name: String,
+    pub label: Option<String>,
+    pub span: token::Span,
+}
+
+/// `@<ruby>(漢字, かんじ)` for the whole word, or `@<ruby>(漢字, かん|じ)`
+/// (also `@<ruby>(漢|字, かん|じ)`) with a reading per character or group.
+///
+/// `base` and `text` have the same number of groups.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ruby {
+    pub base: Vec<String>,
+    pub text: Vec<String>,
+}
+
+/// `@user`, or `@user@host` for a fediverse address.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mention {
+    pub user: String,
+    pub host: Option<String>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Func {
     pub name: String,
-    pub arg: Option<Vec<String>>,
+    pub arg: Vec<token::Literal>,
+    pub named_arg: Vec<(String, token::Literal)>,
+    /// The arguments in order with their keys, unquoted and unescaped but
+    /// not parsed as literals; read them with `get_arg_str`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub raw_arg: Vec<(Option<String>, String)>,
     pub block: Option<String>,
+    pub span: token::Span,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header {
     pub doctype: Option<()>,
     pub title: Option<String>,
     pub author: Option<String>,
     pub description: Option<String>,
+    pub fields: Vec<(String, token::Literal)>,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "type", content = "data", rename_all = "snake_case")
+)]
 pub enum Block {
-    Code(String),
+    Code(Code),
     Quote,
 }
 
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Code {
+    pub lang: Option<String>,
+    pub tag: Option<String>,
+    pub src: String,
+}
+
+impl Node {
+    /// Visit `self` and every descendant node in document order.
+    pub fn walk<'a, F>(&'a self, f: &mut F)
+    where
+        F: FnMut(&'a Node),
+    {
+        f(self);
+        if let Node::Section(s) = self {
+            for c in &s.child {
+                c.walk(f);
+            }
+        }
+    }
+
+    /// Inline content held directly by this node: the children of a
+    /// paragraph, or of every list item or table cell.
+    pub fn inline(&self) -> Vec<&[ParagraphChild]> {
+        let mut v = Vec::new();
+        match self {
+            Node::Paragraph(p) => v.push(p.child.as_slice()),
+            Node::List(l) => l.inline(&mut v),
+            Node::Table(t) => {
+                for r in t.header.iter().chain(&t.body) {
+                    v.extend(r.iter().map(|c| c.child.as_slice()));
+                }
+            }
+            _ => {}
+        }
+        v
+    }
+
+    pub fn inline_mut(&mut self) -> Vec<&mut Vec<ParagraphChild>> {
+        let mut v = Vec::new();
+        match self {
+            Node::Paragraph(p) => v.push(&mut p.child),
+            Node::List(l) => l.inline_mut(&mut v),
+            Node::Table(t) => {
+                for r in t.header.iter_mut().chain(&mut t.body) {
+                    v.extend(r.iter_mut().map(|c| &mut c.child));
+                }
+            }
+            _ => {}
+        }
+        v
+    }
+
+    pub fn walk_mut<F>(&mut self, f: &mut F)
+    where
+        F: FnMut(&mut Node),
+    {
+        f(self);
+        if let Node::Section(s) = self {
+            for c in &mut s.child {
+                c.walk_mut(f);
+            }
+        }
+    }
+}
+
+impl Link {
+    pub fn new(url: &str) -> Self {
+        Self {
+            kind: link::kind_of(url),
+            url: url.to_string(),
+            label: None,
+            title: None,
+            span: token::Span::default(),
+        }
+    }
+
+    /// The label, or the URL itself for bare links.
+    pub fn text(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.url)
+    }
+
+    /// The URL to point at, with `mailto:` added for email addresses.
+    pub fn href(&self) -> String {
+        match self.kind {
+            link::Link::Email if !self.url.starts_with("mailto:") => {
+                format!("mailto:{}", self.url)
+            }
+            _ => self.url.clone(),
+        }
+    }
+}
+
+impl Mention {
+    pub fn new(s: &str) -> Self {
+        match s.split_once('@') {
+            Some((user, host)) => Self {
+                user: user.to_string(),
+                host: Some(host.to_string()),
+            },
+            None => Self {
+                user: s.to_string(),
+                host: None,
+            },
+        }
+    }
+
+    pub fn is_fediverse(&self) -> bool {
+        self.host.is_some()
+    }
+}
+
+impl Ruby {
+    pub fn new(base: &str, text: &str) -> Self {
+        let text: Vec<String> = text.split('|').map(|t| t.to_string()).collect();
+        let base: Vec<String> = if base.contains('|') {
+            base.split('|').map(|b| b.to_string()).collect()
+        } else if text.len() > 1 {
+            base.chars().map(|c| c.to_string()).collect()
+        } else {
+            vec![base.to_string()]
+        };
+
+        // groups that do not line up annotate the whole word
+        if base.len() != text.len() {
+            return Self {
+                base: vec![base.concat()],
+                text: vec![text.concat()],
+            };
+        }
+        Self { base, text }
+    }
+
+    pub fn is_grouped(&self) -> bool {
+        self.base.len() > 1
+    }
+}
+
+/// Plain-text fallback: `漢字（かんじ）`.
+impl std::fmt::Display for Ruby {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}（{}）", self.base.concat(), self.text.concat())
+    }
+}
+
+impl std::fmt::Display for Mention {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.host {
+            Some(h) => write!(f, "@{}@{}", self.user, h),
+            None => write!(f, "@{}", self.user),
+        }
+    }
+}
+
+impl Func {
+    pub fn get_arg(&self, i: usize) -> Option<&token::Literal> {
+        self.arg.get(i)
+    }
+
+    pub fn get_named_arg(&self, key: &str) -> Option<&token::Literal> {
+        self.named_arg
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+
+    /// Argument `i` as written, for names, URLs and paths: `@<jmp>(01)`
+    /// refers to the tag `01`, while `get_arg(0)` is `Literal::Int(1)`.
+    pub fn get_arg_str(&self, i: usize) -> Option<String> {
+        match self.raw_arg.iter().filter(|(k, _)| k.is_none()).nth(i) {
+            Some((_, s)) => Some(s.clone()),
+            // built without the source, e.g. by an importer
+            None => self.get_arg(i).map(|a| a.to_string()),
+        }
+    }
+
+    /// The named argument `key` as written; see `get_arg_str`.
+    pub fn get_named_arg_str(&self, key: &str) -> Option<String> {
+        match self.raw_arg.iter().find(|(k, _)| k.as_deref() == Some(key)) {
+            Some((_, s)) => Some(s.clone()),
+            None => self.get_named_arg(key).map(|a| a.to_string()),
+        }
+    }
+}
+
+impl Header {
+    pub fn get(&self, key: &str) -> Option<&token::Literal> {
+        self.fields.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+}
+
 impl Section {
+    /// A trailing `@[tag]` in the title tags the section.
     pub fn new(title: token::Title) -> Self {
+        let mut name = title.name;
+        let mut tag = None;
+
+        let trimmed = name.trim_end();
+        if let Some(t) = trimmed.strip_suffix(']') {
+            if let Some(i) = t.rfind("@[") {
+                tag = Some(t[i + 2..].to_string());
+                name = t[..i].trim_end().to_string();
+            }
+        }
+
         Self {
             level: title.level,
-            title: title.name,
+            title: token::unescape_text(&name),
+            tag,
             child: vec![],
         }
     }
 }
+
+impl List {
+    fn inline<'a>(&'a self, v: &mut Vec<&'a [ParagraphChild]>) {
+        for i in &self.items {
+            v.push(&i.child);
+            if let Some(l) = &i.list {
+                l.inline(v);
+            }
+        }
+    }
+
+    fn inline_mut<'a>(&'a mut self, v: &mut Vec<&'a mut Vec<ParagraphChild>>) {
+        for i in &mut self.items {
+            v.push(&mut i.child);
+            if let Some(l) = &mut i.list {
+                l.inline_mut(v);
+            }
+        }
+    }
+}
+
+impl Table {
+    pub fn columns(&self) -> usize {
+        self.header
+            .iter()
+            .chain(self.body.iter())
+            .map(|r| r.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn align(&self, column: usize) -> Align {
+        self.align.get(column).copied().unwrap_or(Align::Default)
+    }
+}
+
+impl Paragraph {
+    /// The tag if the paragraph is nothing but a single `@[tag]`.
+    pub fn tag_only(&self) -> Option<String> {
+        match self.child.as_slice() {
+            [ParagraphChild::Tag(t)] => Some(t.clone()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json() {
+        use crate::*;
+
+        let s = "---\ntitle: JSON\ndate: 2021-04-01\n---\n\n= Intro @[intro]\n\nsee @<jmp>(intro)\n\n- [x] $x$\n";
+        let ast = parse(s).unwrap();
+        let v = serde_json::to_value(&ast).unwrap();
+
+        let root = &v["data"]["child"];
+        assert_eq!(root[0]["type"], "header");
+        assert_eq!(
+            root[0]["data"]["fields"][1][1],
+            serde_json::json!({"type": "date", "data": {"year": 2021, "month": 4, "day": 1}})
+        );
+        let intro = &root[1]["data"];
+        assert_eq!(intro["tag"], "intro");
+        assert_eq!(
+            intro["child"][0],
+            serde_json::json!({"type": "paragraph", "data": {"tag": null, "child": [
+                {"type": "sentence", "data": "see "},
+                {"type": "jump", "data": {"tag": "intro", "span": {"start": 60, "end": 73}}}
+            ]}})
+        );
+        assert_eq!(
+            intro["child"][1]["data"]["items"][0]["child"][0],
+            serde_json::json!({"type": "math", "data": {"src": "x", "display": false}})
+        );
+
+        let back: ast::Node = serde_json::from_value(v).unwrap();
+        assert_eq!(format::format(&back), format::format(&ast));
+    }
+}
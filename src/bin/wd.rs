@@ -0,0 +1,118 @@
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use std::process;
+
+use clap::{App, Arg};
+
+use writedown::check::Checker;
+use writedown::diagnostic::{Diagnostic, Level};
+use writedown::render::{text, Backend, Context};
+use writedown::{csv, link};
+
+fn main() {
+    let matches = App::new("wd")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about(env!("CARGO_PKG_DESCRIPTION"))
+        .subcommand_required(true)
+        .subcommand(
+            App::new("check")
+                .about("check internal references without network access")
+                .arg(
+                    Arg::with_name("SRC")
+                        .help("source files")
+                        .required(true)
+                        .multiple_values(true),
+                ),
+        )
+        .subcommand(
+            App::new("cat")
+                .about("show a document on the terminal")
+                .arg(Arg::with_name("SRC").help("source file").required(true))
+                .arg(
+                    Arg::with_name("width")
+                        .long("width")
+                        .short('w')
+                        .takes_value(true)
+                        .help("line width; $COLUMNS or 80 by default"),
+                )
+                .arg(
+                    Arg::with_name("plain")
+                        .long("plain")
+                        .help("no colours, even on a terminal"),
+                ),
+        )
+        .get_matches();
+
+    match matches.subcommand() {
+        Some(("check", m)) => check(m.values_of("SRC").unwrap().collect()),
+        Some(("cat", m)) => {
+            let width = m
+                .value_of("width")
+                .map(|w| w.to_string())
+                .or_else(|| std::env::var("COLUMNS").ok())
+                .and_then(|w| w.parse().ok())
+                .unwrap_or(80);
+            cat(m.value_of("SRC").unwrap(), width, m.is_present("plain"))
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn check(files: Vec<&str>) {
+    let mut checker = Checker::new();
+    let mut errors = 0;
+
+    for f in files {
+        let report = match checker.check_file(f) {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("{}: {}", f, e);
+                errors += 1;
+                continue;
+            }
+        };
+        for d in &report.diagnostics {
+            eprintln!("{}", d.display_with(f, &report.src));
+            if d.level == Level::Error {
+                errors += 1;
+            }
+        }
+    }
+
+    if errors != 0 {
+        process::exit(1);
+    }
+}
+
+fn cat(file: &str, width: usize, plain: bool) {
+    let src = match fs::read_to_string(file) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}: {}", file, e);
+            process::exit(1);
+        }
+    };
+
+    let mut root = match writedown::parse(&src) {
+        Ok(root) => root,
+        Err(e) => {
+            eprintln!("{}", Diagnostic::from(e).display_with(file, &src));
+            process::exit(1);
+        }
+    };
+    let mut diagnostics = link::resolve(&mut root);
+    let dir = Path::new(file).parent().unwrap_or_else(|| Path::new("."));
+    diagnostics.extend(csv::load(&mut root, dir));
+
+    let mut ctx = Context::new(Backend::Text);
+    ctx.text.width = width;
+    ctx.text.numbering = true;
+    ctx.text.ansi = !plain && io::stdout().is_terminal();
+    print!("{}", text::render(&root, &mut ctx));
+
+    for d in diagnostics.iter().chain(&ctx.diagnostics) {
+        eprintln!("{}", d.display_with(file, &src));
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::ast;
+use crate::csv;
+use crate::diagnostic::Diagnostic;
+use crate::link;
+use crate::xref;
+
+/// Diagnostics for one checked file.
+#[derive(Debug)]
+pub struct Report {
+    pub path: PathBuf,
+    pub src: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Offline checker for internal references.
+///
+/// Verifies `@<jmp>` targets, `#tag` anchors, relative file links
+/// (including `file.wd#tag` into other documents) and included files:
+/// images, and CSV files of `@<csvtable>` through `csv::load`. Writedown
+/// has no directive that includes another document, so checking the
+/// contents of included documents is out of scope. External URLs are only
+/// checked for syntax. Documents referred to by other documents are parsed
+/// once and cached.
+#[derive(Default)]
+pub struct Checker {
+    tags: HashMap<PathBuf, Option<HashSet<String>>>,
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse and check a file, resolving reference-style links and loading
+    /// CSV tables first.
+    pub fn check_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<Report> {
+        let path = path.as_ref();
+        let src = fs::read_to_string(path)?;
+
+        let mut root = match crate::parse(&src) {
+            Ok(root) => root,
+            Err(e) => {
+                return Ok(Report {
+                    path: path.to_path_buf(),
+                    src,
+                    diagnostics: vec![e.into()],
+                })
+            }
+        };
+        let mut diagnostics = link::resolve(&mut root);
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        diagnostics.extend(csv::load(&mut root, dir));
+        diagnostics.extend(self.check(&root, dir));
+
+        Ok(Report {
+            path: path.to_path_buf(),
+            src,
+            diagnostics,
+        })
+    }
+
+    /// Check a parsed document whose relative links are relative to `dir`.
+    ///
+    /// `@<linkref>`s are expected to be resolved by `link::resolve` already.
+    pub fn check(&mut self, root: &ast::Node, dir: &Path) -> Vec<Diagnostic> {
+        let mut diagnostics = xref::check(root);
+        let (tags, _) = xref::collect(root);
+
+        let mut links = Vec::new();
+        let mut files = Vec::new();
+        root.walk(&mut |n| {
+            for c in n.inline().into_iter().flatten() {
+                if let ast::ParagraphChild::Link(l) = c {
+                    links.push(l);
+                }
+            }
+            if let ast::Node::Figure(f) = n {
+                files.push((&f.path, f.span));
+            }
+        });
+
+        for (f, span) in files {
+            let err = if link::has_scheme(f) {
+                link::validate_url(f).err()
+            } else if !dir.join(f).exists() {
+                Some(format!("`{}` does not exist", dir.join(f).display()))
+            } else {
+                None
+            };
+            if let Some(e) = err {
+                let d = Diagnostic::error(format!("broken file reference `{}`: {}", f, e));
+                diagnostics.push(d.with_span(span));
+            }
+        }
+
+        for l in links {
+            let err = match l.kind {
+                _ if link::has_scheme(&l.url) => link::validate_url(&l.href()).err(),
+                link::Link::Email => link::validate_url(&l.href()).err(),
+                _ => self.check_relative(&l.url, dir, &|t| tags.contains_key(t)),
+            };
+            if let Some(e) = err {
+                let d = Diagnostic::error(format!("broken link `{}`: {}", l.url, e));
+                diagnostics.push(d.with_span(l.span));
+            }
+        }
+
+        diagnostics
+    }
+
+    fn check_relative(
+        &mut self,
+        url: &str,
+        dir: &Path,
+        local: &dyn Fn(&str) -> bool,
+    ) -> Option<String> {
+        let (file, tag) = match url.split_once('#') {
+            Some((f, t)) => (f, Some(t)),
+            None => (url, None),
+        };
+
+        if file.is_empty() {
+            return match tag {
+                Some(t) if !local(t) => Some(format!("no tag `{}` in this document", t)),
+                _ => None,
+            };
+        }
+
+        let path = dir.join(file);
+        if !path.exists() {
+            return Some(format!("`{}` does not exist", path.display()));
+        }
+        let tag = tag?;
+        match self.tags_of(&path) {
+            Some(tags) if !tags.contains(tag) => {
+                Some(format!("no tag `{}` in `{}`", tag, path.display()))
+            }
+            // not a Writedown document; nothing to look into
+            _ => None,
+        }
+    }
+
+    fn tags_of(&mut self, path: &Path) -> Option<&HashSet<String>> {
+        self.tags
+            .entry(path.to_path_buf())
+            .or_insert_with(|| {
+                if path.extension()? != "wd" {
+                    return None;
+                }
+                let src = fs::read_to_string(path).ok()?;
+                let root = crate::parse(&src).ok()?;
+                let (tags, _) = xref::collect(&root);
+                Some(tags.into_keys().collect())
+            })
+            .as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::check::Checker;
+
+    #[test]
+    fn check() {
+        let dir = std::env::temp_dir().join(format!("writedown-check-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("other.wd"), "= other @[there]\n").unwrap();
+
+        let src = r#"= intro @[intro]
+
+@<jmp>(intro) @<jmp>(nowhere)
+@<link>(#intro){ok} @<link>(#missing){anchor}
+@<link>(other.wd#there){ok} @<link>(other.wd#gone){tag}
+@<link>(nothing.wd){file}
+@<link>(https://sksat.net/){ok} @<link>(https://bad..host/){url}
+"#;
+        let path = dir.join("main.wd");
+        fs::write(&path, src).unwrap();
+
+        let r = Checker::new().check_file(&path).unwrap();
+        let msg: Vec<String> = r
+            .diagnostics
+            .iter()
+            .map(|d| d.display_with("main.wd", &r.src))
+            .collect();
+
+        let broken = dir.join("broken.wd");
+        fs::write(&broken, "text\n\n```\nnever closed\n").unwrap();
+        let b = Checker::new().check_file(&broken).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            b.diagnostics[0].display_with("broken.wd", &b.src),
+            "broken.wd:3:1: error: no end mark of code block"
+        );
+
+        assert_eq!(msg.len(), 5, "{:#?}", msg);
+        assert!(msg[0].starts_with("main.wd:3:15: error: `@<jmp>(nowhere)`"));
+        assert!(msg[1].starts_with("main.wd:4:21: error: broken link `#missing`"));
+        assert!(msg[2].contains("no tag `gone`"));
+        assert!(msg[3].contains("nothing.wd"));
+        assert!(msg[4].contains("invalid host `bad..host`"));
+    }
+}
@@ -0,0 +1,228 @@
+use std::fs;
+use std::path::Path;
+
+use crate::ast;
+use crate::diagnostic::Diagnostic;
+
+/// Split CSV (RFC 4180) or TSV text into records.
+///
+/// Fields may be quoted with `"`, in which case they can contain the
+/// delimiter, newlines and `""` for a literal quote.
+pub fn parse(src: &str, delimiter: char) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+
+    let mut it = src.chars().peekable();
+    while let Some(c) = it.next() {
+        if quoted {
+            match c {
+                '"' if it.peek() == Some(&'"') => {
+                    it.next();
+                    field.push('"');
+                }
+                '"' => quoted = false,
+                _ => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c)
+                }
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.is_empty() => quoted = true,
+            '\r' => {}
+            '\n' => {
+                line += 1;
+                record.push(std::mem::take(&mut field));
+                // skip blank lines
+                if record.len() > 1 || !record[0].is_empty() {
+                    records.push(std::mem::take(&mut record));
+                }
+                record.clear();
+            }
+            _ if c == delimiter => record.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(format!("unterminated quoted field at line {}", line));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Build a table; with `header`, the first record is the header row.
+pub fn table(records: Vec<Vec<String>>, header: bool) -> ast::Table {
+    let row = |r: Vec<String>| {
+        r.into_iter()
+            .map(|c| ast::Cell {
+                child: vec![ast::ParagraphChild::Sentence(c)],
+            })
+            .collect()
+    };
+
+    let mut body: Vec<Vec<ast::Cell>> = records.into_iter().map(row).collect();
+    let header = if header && !body.is_empty() {
+        vec![body.remove(0)]
+    } else {
+        Vec::new()
+    };
+
+    ast::Table {
+        caption: None,
+        tag: None,
+        align: Vec::new(),
+        header,
+        body,
+    }
+}
+
+/// Options of `@<csvtable>(path, header=true, delimiter=tab, caption=...)`.
+///
+/// A letter is not a delimiter: `delimiter=\t` reads as `t` after
+/// unescaping, so it is rejected rather than splitting on `t`.
+pub(crate) fn table_from_func(f: &ast::Func, src: &str) -> Result<ast::Table, String> {
+    let path = f.get_arg_str(0);
+    let delimiter = match f.get_named_arg_str("delimiter") {
+        Some(d) if d == "tab" => '\t',
+        Some(d) if d.len() == 1 && d.as_bytes()[0].is_ascii_alphabetic() => {
+            return Err(format!(
+                "invalid delimiter `{}` (write `delimiter=tab` for a tab)",
+                d
+            ))
+        }
+        Some(d) if d.chars().count() == 1 => d.chars().next().unwrap(),
+        Some(d) => return Err(format!("invalid delimiter `{}`", d)),
+        None if path.as_deref().is_some_and(|p| p.ends_with(".tsv")) => '\t',
+        None => ',',
+    };
+    let header = f
+        .get_named_arg("header")
+        .and_then(|h| h.as_bool())
+        .unwrap_or(false);
+
+    let mut table = table(parse(src, delimiter)?, header);
+    table.caption = f.get_named_arg_str("caption");
+    Ok(table)
+}
+
+/// Load `@<csvtable>(path, ...)` tables from files relative to `dir`.
+///
+/// Inline `@<csvtable>{...}` blocks are converted by the parser already.
+pub fn load(root: &mut ast::Node, dir: &Path) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    root.walk_mut(&mut |n| {
+        let s = match n {
+            ast::Node::Section(s) => s,
+            _ => return,
+        };
+        for c in &mut s.child {
+            let p = match c {
+                ast::Node::Paragraph(p) => p,
+                _ => continue,
+            };
+            let f = match p.child.as_slice() {
+                [ast::ParagraphChild::Func(f)] if f.name == "csvtable" => f,
+                _ => continue,
+            };
+            let path = match f.get_arg_str(0) {
+                Some(p) => dir.join(p),
+                None => continue,
+            };
+
+            let t = fs::read_to_string(&path)
+                .map_err(|e| format!("{}: {}", path.display(), e))
+                .and_then(|src| table_from_func(f, &src));
+            match t {
+                Ok(mut t) => {
+                    t.tag = p.tag.take();
+                    *c = ast::Node::Table(t);
+                }
+                Err(e) => diagnostics
+                    .push(Diagnostic::error(format!("cannot load table: {}", e)).with_span(f.span)),
+            }
+        }
+    });
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::*;
+
+    #[test]
+    fn records() {
+        let r = csv::parse("a,\"b,\"\"c\"\"\"\n\n1,\"2\n3\"\r\n", ',').unwrap();
+        assert_eq!(r, vec![vec!["a", "b,\"c\""], vec!["1", "2\n3"]]);
+        let r = csv::parse("a\tb\n1\t2", '\t').unwrap();
+        assert_eq!(r, vec![vec!["a", "b"], vec!["1", "2"]]);
+        assert!(csv::parse("\"a", ',').is_err());
+    }
+
+    #[test]
+    fn load() {
+        let dir = std::env::temp_dir().join(format!("writedown-csv-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("bench.tsv"), "name\ttime\nfoo\t1.2\n").unwrap();
+
+        let s = r#"@[bench]
+
+@<csvtable>(bench.tsv, header=true, caption=Benchmark)
+
+@<csvtable>(missing.csv)
+
+@<csvtable>(delimiter=";"){
+a;b
+}
+"#;
+        let mut ast = parse(s).unwrap();
+        let diag = csv::load(&mut ast, &dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(diag.len(), 1);
+        assert!(diag[0].message.contains("missing.csv"));
+
+        let s = match ast {
+            ast::Node::Section(s) => s,
+            _ => panic!(),
+        };
+        let t = match &s.child[0] {
+            ast::Node::Table(t) => t,
+            _ => panic!(),
+        };
+        assert_eq!(t.tag.as_deref(), Some("bench"));
+        assert_eq!(t.caption.as_deref(), Some("Benchmark"));
+        assert_eq!((t.header.len(), t.body.len(), t.columns()), (1, 1, 2));
+        let t = match &s.child[2] {
+            ast::Node::Table(t) => t,
+            _ => panic!(),
+        };
+        assert_eq!((t.header.len(), t.body.len(), t.columns()), (0, 1, 2));
+
+        let s = "text\n\n@<csvtable>(delimiter=\"::\"){\na::b\n}\n";
+        let e = parse(s).unwrap_err();
+        assert_eq!(e.message, "invalid table: invalid delimiter `::`");
+        assert_eq!(e.span.start, 6);
+        for d in ["\\t", "\"\\t\""] {
+            let s = format!("@<csvtable>(delimiter={}){{\na\tb\n}}\n", d);
+            let e = parse(&s).unwrap_err();
+            assert!(e.message.contains("delimiter `t`"), "{}", e);
+        }
+        let s = "@<csvtable>{\n\"a\n}\n";
+        assert!(parse(s).unwrap_err().message.starts_with("invalid table: "));
+    }
+}
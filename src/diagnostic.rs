@@ -0,0 +1,61 @@
+use std::fmt;
+
+use crate::token::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl Diagnostic {
+    pub fn error<S: Into<String>>(message: S) -> Self {
+        Self {
+            level: Level::Error,
+            message: message.into(),
+            span: None,
+        }
+    }
+
+    pub fn warning<S: Into<String>>(message: S) -> Self {
+        Self {
+            level: Level::Warning,
+            message: message.into(),
+            span: None,
+        }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    /// `file:line:col: level: message`, or without the position if there
+    /// is no span.
+    pub fn display_with(&self, file: &str, src: &str) -> String {
+        match self.span {
+            Some(s) => {
+                let (l, c) = s.line_col(src);
+                format!("{}:{}:{}: {}", file, l, c, self)
+            }
+            None => format!("{}: {}", file, self),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let l = match self.level {
+            Level::Error => "error",
+            Level::Warning => "warning",
+        };
+        write!(f, "{}: {}", l, self.message)
+    }
+}
@@ -0,0 +1,432 @@
+use crate::ast;
+use crate::link;
+use crate::token::{self, Literal};
+
+/// Print a document back as Writedown source.
+///
+/// Text is escaped with `escape`, so parsing the output gives the same
+/// tree. Blocks are separated by a blank line.
+///
+/// A mention is only recognized after whitespace, so one that directly
+/// follows a word (e.g. from `import::markdown`) gets a space in front.
+/// Functions and tags are recognized anywhere and are written as they are.
+pub fn format(node: &ast::Node) -> String {
+    let mut blocks = Vec::new();
+    format_node(node, &mut blocks);
+    blocks.join("\n")
+}
+
+fn format_node(node: &ast::Node, blocks: &mut Vec<String>) {
+    match node {
+        ast::Node::Header(h) => {
+            let mut s = String::from("---\n");
+            for (k, v) in &h.fields {
+                s += &format!("{}: {}\n", k, literal(v));
+            }
+            s += "---\n";
+            blocks.push(s);
+        }
+        ast::Node::Section(s) => {
+            if s.level != 0 {
+                let tag = s
+                    .tag
+                    .as_ref()
+                    .map(|t| format!(" @[{}]", t))
+                    .unwrap_or_default();
+                blocks.push(format!(
+                    "{} {}{}\n",
+                    "=".repeat(s.level),
+                    escape(&s.title),
+                    tag
+                ));
+            }
+            for c in &s.child {
+                format_node(c, blocks);
+            }
+        }
+        ast::Node::Paragraph(p) => {
+            push_tag(&p.tag, blocks);
+            if !p.child.is_empty() {
+                blocks.push(format!("{}\n", format_children(&p.child)));
+            }
+        }
+        ast::Node::Figure(f) => {
+            push_tag(&f.tag, blocks);
+            let mut arg = vec![literal(&Literal::Str(f.path.clone()))];
+            if let Some(a) = &f.alt {
+                arg.push(format!("alt={}", literal(&Literal::Str(a.clone()))));
+            }
+            if let Some(w) = &f.width {
+                arg.push(format!("width={}", literal(w)));
+            }
+            let caption = f
+                .caption
+                .as_ref()
+                .map(|c| format!("{{{}}}", escape_block(c)))
+                .unwrap_or_default();
+            blocks.push(format!("@<img>({}){}\n", arg.join(", "), caption));
+        }
+        ast::Node::Table(t) => {
+            push_tag(&t.tag, blocks);
+            blocks.push(format_table(t));
+        }
+        ast::Node::List(l) => blocks.push(format_list(l, "")),
+        ast::Node::Block(ast::Block::Code(c)) => {
+            push_tag(&c.tag, blocks);
+            let lang = c
+                .lang
+                .as_ref()
+                .map(|l| format!(":{}", l))
+                .unwrap_or_default();
+            blocks.push(format!("```{}\n{}```\n", lang, c.src));
+        }
+        ast::Node::Comment(c) if c.block => blocks.push(format!("#[ {} ]#\n", c.body)),
+        ast::Node::Comment(c) if c.body.is_empty() => blocks.push("#\n".to_string()),
+        ast::Node::Comment(c) => blocks.push(format!("# {}\n", c.body)),
+        _ => {}
+    }
+}
+
+// a `@[tag]` paragraph of its own attaches to the next block
+fn push_tag(tag: &Option<String>, blocks: &mut Vec<String>) {
+    if let Some(t) = tag {
+        blocks.push(format!("@[{}]\n", t));
+    }
+}
+
+fn format_list(l: &ast::List, indent: &str) -> String {
+    let mut s = String::new();
+    for (n, i) in l.items.iter().enumerate() {
+        let marker = match l.start {
+            Some(start) => format!("{}. ", start + n),
+            None => "- ".to_string(),
+        };
+        let check = match i.check {
+            Some(true) => "[x] ",
+            Some(false) => "[ ] ",
+            None => "",
+        };
+        let inner = format!("{}{}", indent, " ".repeat(marker.len()));
+        let text = format_children(&i.child).replace('\n', &format!("\n{}", inner));
+        s += &format!("{}{}{}{}\n", indent, marker, check, text);
+        if let Some(l) = &i.list {
+            s += &format_list(l, &inner);
+        }
+    }
+    s
+}
+
+fn format_table(t: &ast::Table) -> String {
+    let row = |r: &[ast::Cell]| {
+        let cells: Vec<String> = r
+            .iter()
+            .map(|c| format_children(&c.child).replace('|', "\\|"))
+            .collect();
+        format!("| {} |\n", cells.join(" | "))
+    };
+
+    let mut s = String::new();
+    for r in &t.header {
+        s += &row(r);
+    }
+    if !t.header.is_empty() || !t.align.is_empty() {
+        let sep: Vec<&str> = (0..t.columns())
+            .map(|i| match t.align(i) {
+                ast::Align::Default => "---",
+                ast::Align::Left => ":--",
+                ast::Align::Center => ":-:",
+                ast::Align::Right => "--:",
+            })
+            .collect();
+        s += &format!("|{}|\n", sep.join("|"));
+    }
+    for r in &t.body {
+        s += &row(r);
+    }
+
+    match &t.caption {
+        Some(c) => format!(
+            "@<table>({}){{\n{}}}\n",
+            literal(&Literal::Str(c.clone())),
+            s
+        ),
+        None => s,
+    }
+}
+
+fn format_children(child: &[ast::ParagraphChild]) -> String {
+    let mut s = String::new();
+    for (i, c) in child.iter().enumerate() {
+        let f = match c {
+            ast::ParagraphChild::Sentence(t) => escape(t),
+            ast::ParagraphChild::SoftBreak => "\n".to_string(),
+            ast::ParagraphChild::Func(f) => {
+                // `@<f>` followed by `(` needs an explicit empty argument list
+                let paren = matches!(
+                    child.get(i + 1),
+                    Some(ast::ParagraphChild::Sentence(t)) if t.starts_with('(')
+                );
+                format_func(f, paren)
+            }
+            ast::ParagraphChild::Tag(t) => format!("@[{}]", t),
+            ast::ParagraphChild::Jump(j) => format!("@<jmp>({})", literal_str(&j.tag)),
+            ast::ParagraphChild::Footnote(ft) => match &ft.tag {
+                Some(t) => format!("@<ftref>({}){{{}}}", literal_str(t), escape_block(&ft.body)),
+                None => format!("@<ft>{{{}}}", escape_block(&ft.body)),
+            },
+            ast::ParagraphChild::Mention(m) => m.to_string(),
+            ast::ParagraphChild::Math(m) => m.to_string(),
+            // inline code cannot hold a backquote, e.g. from Markdown
+            ast::ParagraphChild::Code(c) if c.contains('`') => {
+                format!("@<code>{{{}}}", escape_block(c))
+            }
+            ast::ParagraphChild::Code(c) => format!("`{}`", c),
+            ast::ParagraphChild::Ruby(r) => {
+                let base = if r.is_grouped() && r.base.iter().any(|b| b.chars().count() > 1) {
+                    r.base.join("|")
+                } else {
+                    r.base.concat()
+                };
+                format!(
+                    "@<ruby>({}, {})",
+                    literal_str(&base),
+                    literal_str(&r.text.join("|"))
+                )
+            }
+            ast::ParagraphChild::Link(l) => format_link(l),
+            ast::ParagraphChild::LinkDef(d) => {
+                let mut arg = vec![literal_str(&d.name), literal_str(&d.link.url)];
+                if let Some(t) = &d.link.title {
+                    arg.push(format!("title={}", literal_str(t)));
+                }
+                format!("@<linkdef>({})", arg.join(", "))
+            }
+            ast::ParagraphChild::LinkRef(r) => {
+                let label = r
+                    .label
+                    .as_ref()
+                    .map(|l| format!("{{{}}}", escape_block(l)))
+                    .unwrap_or_default();
+                format!("@<linkref>({}){}", literal_str(&r.name), label)
+            }
+        };
+        let mention = matches!(c, ast::ParagraphChild::Mention(_));
+        if mention && s.ends_with(|c: char| !c.is_whitespace()) {
+            s.push(' ');
+        }
+        s += &f;
+    }
+    s
+}
+
+fn format_func(f: &ast::Func, paren: bool) -> String {
+    let arg: Vec<String> = if f.raw_arg.len() == f.arg.len() + f.named_arg.len() {
+        // as written where that reads back the same, so `01` stays `01`
+        let (mut arg, mut named_arg) = (f.arg.iter(), f.named_arg.iter());
+        let as_written = |l: &Literal, raw: &str| {
+            if is_bare(raw, l) {
+                raw.to_string()
+            } else {
+                literal(l)
+            }
+        };
+        f.raw_arg
+            .iter()
+            .map(|(k, raw)| match k {
+                Some(k) => format!("{}={}", k, as_written(&named_arg.next().unwrap().1, raw)),
+                None => as_written(arg.next().unwrap(), raw),
+            })
+            .collect()
+    } else {
+        let mut arg: Vec<String> = f.arg.iter().map(literal).collect();
+        for (k, v) in &f.named_arg {
+            arg.push(format!("{}={}", k, literal(v)));
+        }
+        arg
+    };
+
+    let mut s = format!("@<{}>", f.name);
+    if !arg.is_empty() || paren {
+        s += &format!("({})", arg.join(", "));
+    }
+    if let Some(b) = &f.block {
+        // kept as written
+        s += &format!("{{{}}}", b);
+    }
+    s
+}
+
+fn format_link(l: &ast::Link) -> String {
+    // bare URLs and addresses are found again by the parser
+    let auto = l.label.is_none()
+        && l.title.is_none()
+        && link::find(&l.url).is_some_and(|(b, e, _)| b == 0 && e == l.url.len());
+    if auto {
+        return l.url.clone();
+    }
+
+    let mut arg = vec![literal_str(&l.url)];
+    if let Some(t) = &l.title {
+        arg.push(format!("title={}", literal_str(t)));
+    }
+    let label = l
+        .label
+        .as_ref()
+        .map(|l| format!("{{{}}}", escape_block(l)))
+        .unwrap_or_default();
+    format!("@<link>({}){}", arg.join(", "), label)
+}
+
+fn literal_str(s: &str) -> String {
+    literal(&Literal::Str(s.to_string()))
+}
+
+// whether `s` can be written unquoted and reads back as `l`
+fn is_bare(s: &str, l: &Literal) -> bool {
+    let special = |c: char| matches!(c, ',' | '(' | ')' | '[' | ']' | '"' | '\\' | '=');
+    !s.is_empty() && s.trim() == s && !s.contains(special) && Literal::parse(s) == *l
+}
+
+/// Source form of a function argument or header value.
+fn literal(l: &Literal) -> String {
+    match l {
+        Literal::Str(s) => {
+            if is_bare(s, l) {
+                return s.clone();
+            }
+            let mut q = String::from("\"");
+            for c in s.chars() {
+                if c == '"' || c == '\\' {
+                    q.push('\\');
+                }
+                q.push(c);
+            }
+            q.push('"');
+            q
+        }
+        // keep `1.0` a float
+        Literal::Float(f) if f.is_finite() && f.fract() == 0.0 => format!("{:.1}", f),
+        Literal::List(v) => {
+            let v: Vec<String> = v.iter().map(literal).collect();
+            format!("[{}]", v.join(", "))
+        }
+        _ => l.to_string(),
+    }
+}
+
+/// Escape sentence text with backslashes (see `token::ESCAPABLE`).
+///
+/// `@` is escaped where it would start a function, tag or mention, `=`,
+/// `` ` ``, `#` and `|` at the beginning where they would start a line, and
+/// `{`, `}` and `$` everywhere.
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut before: Option<char> = None;
+    let mut it = s.chars().peekable();
+    while let Some(c) = it.next() {
+        let esc = match c {
+            '\\' => it.peek().is_none_or(|n| token::ESCAPABLE.contains(n)),
+            '@' => {
+                before.is_none_or(|b| b.is_whitespace())
+                    || it.peek().is_some_and(|n| matches!(n, '<' | '['))
+            }
+            '=' | '#' | '|' => before.is_none(),
+            '{' | '}' | '$' | '`' => true,
+            _ => false,
+        };
+        if esc {
+            out.push('\\');
+        }
+        out.push(c);
+        before = Some(c);
+    }
+    out
+}
+
+// text inside `{...}`
+pub(crate) fn escape_block(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut it = s.chars().peekable();
+    while let Some(c) = it.next() {
+        let esc = match c {
+            '\\' => it.peek().is_none_or(|n| token::ESCAPABLE.contains(n)),
+            '{' | '}' => true,
+            _ => false,
+        };
+        if esc {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn round_trip() {
+        let s = r#"---
+title: "Hello, world"
+version: 1.0
+---
+
+= Intro @[intro]
+
+see @<jmp>(intro) and https://sksat.net/
+@sksat@mstdn.maud.io
+\# not a comment
+
+@[code]
+
+```:rust
+fn main() {}
+```
+
+== Data
+
+@<table>(Result){
+| a | b |
+|:--|--:|
+| 1 | x\|y |
+}
+
+@<img>(fig.png, alt="a, b", width=0.5){caption \}}
+
+@<f>()(not an argument) @<g>(01, n=1.10) @<ft>{note} @<link>(https://example.com, title=Example){ex}
+
+# note
+"#;
+        let opts = parse::Options {
+            keep_comments: true,
+            line_join: linebreak::LineJoin::Keep,
+        };
+        let ast = parse_with_options(s, opts.clone()).unwrap();
+        let f = format::format(&ast);
+        assert_eq!(f, s);
+        // stable
+        let ast = parse_with_options(&f, opts).unwrap();
+        assert_eq!(format::format(&ast), f);
+    }
+
+    #[test]
+    fn escape() {
+        let s =
+            "\\= not a title \\@<f> x\\@<g> \\@[t] x\\@[u] \\@user \\{x\\} \\$ \\\\@ C:\\path \\`x\\`\n";
+        let ast = parse(s).unwrap();
+        let p = match &ast {
+            ast::Node::Section(s) => match &s.child[0] {
+                ast::Node::Paragraph(p) => p,
+                _ => panic!(),
+            },
+            _ => panic!(),
+        };
+        assert_eq!(p.child.len(), 1);
+        assert!(matches!(
+            &p.child[0],
+            ast::ParagraphChild::Sentence(t) if t == "= not a title @<f> x@<g> @[t] x@[u] @user {x} $ \\@ C:\\path `x`"
+        ));
+        assert_eq!(format::format(&ast), s);
+    }
+}
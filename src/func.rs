@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use crate::ast;
+use crate::render::Context;
+
+/// What a function handler turns an `@<name>` call into.
+#[derive(Debug)]
+pub enum Expansion {
+    /// Nodes rendered in place of the call by the current backend.
+    Nodes(Vec<ast::ParagraphChild>),
+    /// Backend-specific output inserted verbatim.
+    Raw(String),
+}
+
+pub type Handler = dyn Fn(&ast::Func, &mut Context) -> Expansion;
+
+/// Maps `@<name>` to application-defined handlers.
+///
+/// Handlers get the parsed call (arguments and block) and the render
+/// context, so they can look at `ctx.backend` to emit backend-specific
+/// output or push diagnostics.
+#[derive(Default)]
+pub struct FunctionRegistry {
+    handlers: HashMap<String, Box<Handler>>,
+}
+
+impl FunctionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<F>(&mut self, name: &str, handler: F)
+    where
+        F: Fn(&ast::Func, &mut Context) -> Expansion + 'static,
+    {
+        self.handlers.insert(name.to_string(), Box::new(handler));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Handler> {
+        self.handlers.get(name).map(|h| h.as_ref())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.handlers.contains_key(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::func::{Expansion, FunctionRegistry};
+    use crate::render::{html, Backend, Context};
+    use crate::*;
+
+    #[test]
+    fn registry() {
+        let mut reg = FunctionRegistry::new();
+        reg.register("kbd", |f, ctx| {
+            let key = f.block.clone().unwrap_or_default();
+            match ctx.backend {
+                Backend::Html => Expansion::Raw(format!("<kbd>{}</kbd>", key)),
+                #[allow(unreachable_patterns)]
+                _ => Expansion::Nodes(vec![ast::ParagraphChild::Sentence(key)]),
+            }
+        });
+        reg.register("greet", |f, _| {
+            let name = f.get_arg(0).map(|a| a.to_string()).unwrap_or_default();
+            Expansion::Nodes(vec![ast::ParagraphChild::Sentence(format!(
+                "hello, {}",
+                name
+            ))])
+        });
+
+        let ast = parse("press @<kbd>{C-c} @<greet>(sksat) @<nothing>()\n").unwrap();
+        let mut ctx = Context::new(Backend::Html);
+        ctx.registry = Some(&reg);
+        let s = html::render(&ast, &mut ctx);
+
+        assert!(s.contains("<kbd>C-c</kbd>"));
+        assert!(s.contains("hello, sksat"));
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert!(ctx.diagnostics[0].message.contains("nothing"));
+    }
+}
@@ -0,0 +1,519 @@
+use std::collections::HashSet;
+
+use pulldown_cmark::{Alignment, CodeBlockKind, Event, LinkType, Tag};
+
+use crate::ast;
+use crate::diagnostic::Diagnostic;
+use crate::linebreak;
+use crate::parse::{get_block, parse_header, Options};
+use crate::token::{self, Span};
+
+/// Import CommonMark (with GFM tables, task lists, footnotes and
+/// strikethrough) and `---` front matter.
+///
+/// Constructs without a Writedown equivalent are reported as warnings:
+/// emphasis and strikethrough are kept as plain text, block quotes as
+/// ordinary paragraphs, and thematic breaks and raw HTML are dropped. A
+/// footnote referenced more than once is tagged at the first reference and
+/// jumped to from the others. HTML comments become `ast::Node::Comment`
+/// with `Options::keep_comments`.
+pub fn parse(src: &str, options: &Options) -> (ast::Node, Vec<Diagnostic>) {
+    let mut im = Importer::new(options);
+
+    let mut tok = token::Tokenizer::new(src);
+    let mut offset = 0;
+    if let Some(t) = tok.get_header() {
+        im.section()
+            .child
+            .push(ast::Node::Header(parse_header(tok.get_str(&t))));
+        offset = t.span().end;
+    }
+
+    let opts = pulldown_cmark::Options::ENABLE_TABLES
+        | pulldown_cmark::Options::ENABLE_FOOTNOTES
+        | pulldown_cmark::Options::ENABLE_STRIKETHROUGH
+        | pulldown_cmark::Options::ENABLE_TASKLISTS
+        | pulldown_cmark::Options::ENABLE_HEADING_ATTRIBUTES;
+    let parser = pulldown_cmark::Parser::new_ext(&src[offset..], opts);
+    for (e, r) in parser.into_offset_iter() {
+        im.span = Span {
+            start: offset + r.start,
+            end: offset + r.end,
+        };
+        im.event(e);
+    }
+
+    im.finish()
+}
+
+struct Importer<'a> {
+    options: &'a Options,
+    span: Span,
+    diagnostics: Vec<Diagnostic>,
+    // open sections, the root first
+    sections: Vec<ast::Section>,
+    inline: Vec<ast::ParagraphChild>,
+    lists: Vec<ast::List>,
+    items: Vec<ast::ListItem>,
+    // blocks inside list items, written after the list
+    deferred: Vec<ast::Node>,
+    heading: Option<(usize, Option<String>)>,
+    code: Option<ast::Code>,
+    link: Option<ast::Link>,
+    image: Option<(String, String)>,
+    table: Option<ast::Table>,
+    row: Vec<ast::Cell>,
+    footnote: Option<ast::Footnote>,
+    // footnote labels already referenced
+    notes: HashSet<String>,
+}
+
+impl<'a> Importer<'a> {
+    fn new(options: &'a Options) -> Self {
+        Self {
+            options,
+            span: Span::default(),
+            diagnostics: Vec::new(),
+            sections: vec![ast::Section::new(token::Title {
+                level: 0,
+                name: String::new(),
+            })],
+            inline: Vec::new(),
+            lists: Vec::new(),
+            items: Vec::new(),
+            deferred: Vec::new(),
+            heading: None,
+            code: None,
+            link: None,
+            image: None,
+            table: None,
+            row: Vec::new(),
+            footnote: None,
+            notes: HashSet::new(),
+        }
+    }
+
+    fn section(&mut self) -> &mut ast::Section {
+        self.sections.last_mut().unwrap()
+    }
+
+    fn warn(&mut self, what: &str) {
+        self.diagnostics.push(
+            Diagnostic::warning(format!("{} has no Writedown equivalent", what))
+                .with_span(self.span),
+        );
+    }
+
+    fn push_block(&mut self, node: ast::Node) {
+        if self.lists.is_empty() {
+            self.section().child.push(node);
+        } else {
+            self.warn("a block inside a list item");
+            self.deferred.push(node);
+        }
+    }
+
+    fn text(&mut self, s: &str) {
+        if let Some(c) = &mut self.code {
+            c.src += s;
+        } else if let Some((_, alt)) = &mut self.image {
+            *alt += s;
+        } else if let Some(l) = &mut self.link {
+            l.label.get_or_insert_with(String::new).push_str(s);
+        } else if let Some(ast::ParagraphChild::Sentence(t)) = self.inline.last_mut() {
+            *t += s;
+        } else {
+            self.inline
+                .push(ast::ParagraphChild::Sentence(s.to_string()));
+        }
+    }
+
+    fn take_inline(&mut self) -> Vec<ast::ParagraphChild> {
+        let mut child = std::mem::take(&mut self.inline);
+        linebreak::join(&mut child, self.options.line_join);
+        child
+    }
+
+    fn event(&mut self, e: Event) {
+        match e {
+            Event::Start(t) => self.start(t),
+            Event::End(t) => self.end(t),
+            Event::Text(s) => self.text(&s),
+            // a link label or an image description is plain text
+            Event::Code(s) if self.link.is_some() || self.image.is_some() => {
+                self.warn("inline code in a link");
+                self.text(&s);
+            }
+            Event::Code(s) => self.inline.push(ast::ParagraphChild::Code(s.to_string())),
+            Event::Html(s) => {
+                let s = s.trim();
+                match s.strip_prefix("<!--").and_then(|s| s.strip_suffix("-->")) {
+                    Some(c) if self.options.keep_comments => {
+                        let body = c.trim().to_string();
+                        let block = body.contains('\n');
+                        let span = self.span;
+                        self.push_block(ast::Node::Comment(ast::Comment { body, block, span }));
+                    }
+                    Some(_) => {}
+                    None => self.warn("raw HTML"),
+                }
+            }
+            // a tag may be placed once; later references jump to it
+            Event::FootnoteReference(l) if self.notes.contains(l.as_ref()) => {
+                self.warn("a repeated footnote reference");
+                self.inline.push(ast::ParagraphChild::Jump(ast::Jump {
+                    tag: l.to_string(),
+                    span: self.span,
+                }));
+            }
+            Event::FootnoteReference(l) => {
+                self.notes.insert(l.to_string());
+                self.inline.push(ast::ParagraphChild::Tag(l.to_string()))
+            }
+            Event::SoftBreak => self.inline.push(ast::ParagraphChild::SoftBreak),
+            Event::HardBreak => {
+                self.warn("a hard line break");
+                self.inline.push(ast::ParagraphChild::SoftBreak);
+            }
+            Event::Rule => self.warn("a thematic break"),
+            Event::TaskListMarker(done) => {
+                if let Some(i) = self.items.last_mut() {
+                    i.check = Some(done);
+                }
+            }
+        }
+    }
+
+    fn start(&mut self, t: Tag) {
+        match t {
+            Tag::Heading(level, id, _) => {
+                self.heading = Some((level as usize, id.map(|i| i.to_string())))
+            }
+            Tag::BlockQuote => self.warn("a block quote"),
+            Tag::CodeBlock(kind) => {
+                let lang = match kind {
+                    CodeBlockKind::Fenced(l) if !l.is_empty() => Some(l.to_string()),
+                    _ => None,
+                };
+                self.code = Some(ast::Code {
+                    lang,
+                    tag: None,
+                    src: String::new(),
+                });
+            }
+            Tag::List(start) => {
+                // text of a tight item before its nested list
+                if let Some(i) = self.items.last_mut() {
+                    let mut child = std::mem::take(&mut self.inline);
+                    linebreak::join(&mut child, self.options.line_join);
+                    i.child.append(&mut child);
+                }
+                self.lists.push(ast::List {
+                    start: start.map(|s| s as usize),
+                    items: Vec::new(),
+                });
+            }
+            Tag::Item => self.items.push(ast::ListItem {
+                check: None,
+                child: Vec::new(),
+                list: None,
+            }),
+            Tag::FootnoteDefinition(l) => {
+                self.footnote = Some(ast::Footnote {
+                    tag: Some(l.to_string()),
+                    body: String::new(),
+                })
+            }
+            Tag::Table(align) => {
+                let align = align
+                    .iter()
+                    .map(|a| match a {
+                        Alignment::None => ast::Align::Default,
+                        Alignment::Left => ast::Align::Left,
+                        Alignment::Center => ast::Align::Center,
+                        Alignment::Right => ast::Align::Right,
+                    })
+                    .collect();
+                self.table = Some(ast::Table {
+                    caption: None,
+                    tag: None,
+                    align,
+                    header: Vec::new(),
+                    body: Vec::new(),
+                });
+            }
+            Tag::Emphasis => self.warn("emphasis"),
+            Tag::Strong => self.warn("strong emphasis"),
+            Tag::Strikethrough => self.warn("strikethrough"),
+            Tag::Link(kind, url, title) => {
+                let mut l = ast::Link::new(&url);
+                if let LinkType::Email = kind {
+                    l.kind = crate::link::Link::Email;
+                }
+                if !title.is_empty() {
+                    l.title = Some(title.to_string());
+                }
+                l.span = self.span;
+                self.link = Some(l);
+            }
+            Tag::Image(_, url, _) => self.image = Some((url.to_string(), String::new())),
+            Tag::Paragraph | Tag::TableHead | Tag::TableRow | Tag::TableCell => {}
+        }
+    }
+
+    fn end(&mut self, t: Tag) {
+        match t {
+            Tag::Paragraph => {
+                let child = self.take_inline();
+                if let Some(ft) = &mut self.footnote {
+                    if !ft.body.is_empty() {
+                        ft.body += " ";
+                    }
+                    ft.body += &plain(&child);
+                } else if let Some(i) = self.items.last_mut() {
+                    if !i.child.is_empty() {
+                        i.child.push(ast::ParagraphChild::Sentence(" ".to_string()));
+                    }
+                    i.child.extend(child);
+                } else {
+                    self.paragraph(child);
+                }
+            }
+            Tag::Heading(..) => {
+                let (level, tag) = self.heading.take().unwrap();
+                let child = self.take_inline();
+                while self.sections.len() > 1 && self.sections.last().unwrap().level >= level {
+                    self.close_section();
+                }
+                let mut s = ast::Section::new(token::Title {
+                    level,
+                    name: String::new(),
+                });
+                s.title = plain(&child);
+                s.tag = tag;
+                self.sections.push(s);
+            }
+            Tag::CodeBlock(_) => {
+                let code = self.code.take().unwrap();
+                self.push_block(ast::Node::Block(ast::Block::Code(code)));
+            }
+            Tag::List(_) => {
+                let l = self.lists.pop().unwrap();
+                match self.items.last_mut() {
+                    Some(i) => i.list = Some(l),
+                    None => {
+                        self.section().child.push(ast::Node::List(l));
+                        let deferred = std::mem::take(&mut self.deferred);
+                        self.section().child.extend(deferred);
+                    }
+                }
+            }
+            Tag::Item => {
+                let mut child = self.take_inline();
+                let mut i = self.items.pop().unwrap();
+                i.child.append(&mut child);
+                self.lists.last_mut().unwrap().items.push(i);
+            }
+            Tag::FootnoteDefinition(_) => {
+                let ft = self.footnote.take().unwrap();
+                self.section()
+                    .child
+                    .push(ast::Node::Paragraph(ast::Paragraph {
+                        tag: None,
+                        child: vec![ast::ParagraphChild::Footnote(ft)],
+                    }));
+            }
+            Tag::TableCell => {
+                let child = self.take_inline();
+                self.row.push(ast::Cell { child });
+            }
+            Tag::TableHead => {
+                let row = std::mem::take(&mut self.row);
+                self.table.as_mut().unwrap().header.push(row);
+            }
+            Tag::TableRow => {
+                let row = std::mem::take(&mut self.row);
+                self.table.as_mut().unwrap().body.push(row);
+            }
+            Tag::Table(_) => {
+                let t = self.table.take().unwrap();
+                self.push_block(ast::Node::Table(t));
+            }
+            Tag::Link(..) => {
+                let mut l = self.link.take().unwrap();
+                // `<https://...>`
+                if l.label.as_deref() == Some(l.url.as_str()) {
+                    l.label = None;
+                }
+                self.inline.push(ast::ParagraphChild::Link(l));
+            }
+            Tag::Image(_, _, title) => {
+                let (path, alt) = self.image.take().unwrap();
+                let mut named_arg = Vec::new();
+                if !alt.is_empty() {
+                    named_arg.push(("alt".to_string(), token::Literal::Str(alt)));
+                }
+                self.inline.push(ast::ParagraphChild::Func(ast::Func {
+                    name: "img".to_string(),
+                    arg: vec![token::Literal::Str(path)],
+                    named_arg,
+                    raw_arg: Vec::new(),
+                    block: if title.is_empty() {
+                        None
+                    } else {
+                        Some(title.to_string())
+                    },
+                    span: self.span,
+                }));
+            }
+            Tag::BlockQuote | Tag::Emphasis | Tag::Strong | Tag::Strikethrough => {}
+        }
+    }
+
+    // an image on its own becomes a figure, with the title as its caption
+    fn paragraph(&mut self, child: Vec<ast::ParagraphChild>) {
+        let mut p = ast::Paragraph { tag: None, child };
+        match get_block(&mut p, self.options) {
+            Ok(Some(n)) => return self.push_block(n),
+            Ok(None) => {}
+            Err(e) => self.diagnostics.push(e.into()),
+        }
+
+        for c in &mut p.child {
+            if let ast::ParagraphChild::Func(f) = c {
+                let alt = f
+                    .get_named_arg("alt")
+                    .map(|a| a.to_string())
+                    .unwrap_or_default();
+                self.diagnostics.push(
+                    Diagnostic::warning("an inline image has no Writedown equivalent")
+                        .with_span(f.span),
+                );
+                *c = ast::ParagraphChild::Sentence(alt);
+            }
+        }
+        self.push_block(ast::Node::Paragraph(p));
+    }
+
+    fn close_section(&mut self) {
+        let s = self.sections.pop().unwrap();
+        self.section().child.push(ast::Node::Section(s));
+    }
+
+    fn finish(mut self) -> (ast::Node, Vec<Diagnostic>) {
+        while self.sections.len() > 1 {
+            self.close_section();
+        }
+        let root = self.sections.pop().unwrap();
+        (ast::Node::Section(root), self.diagnostics)
+    }
+}
+
+// text of inline content that can only hold a string
+fn plain(child: &[ast::ParagraphChild]) -> String {
+    let mut s = String::new();
+    for c in child {
+        match c {
+            ast::ParagraphChild::Sentence(t) => s += t,
+            ast::ParagraphChild::SoftBreak => s += " ",
+            ast::ParagraphChild::Link(l) => s += l.text(),
+            _ => {}
+        }
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn import() {
+        let md = r#"---
+title: Notes
+---
+
+# Intro {#intro}
+
+Some *text* with a [link](https://example.com "Example")
+and `code`, ``a`b`` and a note[^1].
+
+![a cat](cat.png "The cat")
+
+## Tasks
+
+- [x] done
+- [ ] todo
+  1. nested
+
+| a | b |
+|:--|--:|
+| 1 | 2 |
+
+```rust
+fn main() {}
+```
+
+> quoted @user
+
+[^1]: The note.
+"#;
+        let (ast, diag) = import::markdown::parse(md, &parse::Options::default());
+        assert_eq!(diag.len(), 2);
+        assert!(diag[0].message.starts_with("emphasis"));
+        assert!(diag[1].message.starts_with("a block quote"));
+
+        let f = format::format(&ast);
+        // stable when read back as Writedown
+        assert_eq!(format::format(&parse(&f).unwrap()), f);
+        assert_eq!(
+            f,
+            r#"---
+title: Notes
+---
+
+= Intro @[intro]
+
+Some text with a @<link>(https://example.com, title=Example){link} and `code`, @<code>{a`b} and a note@[1].
+
+@<img>(cat.png, alt=a cat){The cat}
+
+== Tasks
+
+- [x] done
+- [ ] todo
+  1. nested
+
+| a | b |
+|:--|--:|
+| 1 | 2 |
+
+```:rust
+fn main() {}
+```
+
+quoted \@user
+
+@<ftref>("1"){The note.}
+"#
+        );
+    }
+
+    #[test]
+    fn footnote() {
+        let md = "a[^n] and b[^n].\n\n[^n]: The note.\n";
+        let (ast, diag) = import::markdown::parse(md, &parse::Options::default());
+        assert_eq!(diag.len(), 1);
+        assert!(diag[0].message.starts_with("a repeated footnote reference"));
+        assert_eq!(&md[diag[0].span.unwrap().start..][..4], "[^n]");
+        assert!(xref::check(&ast).is_empty());
+        assert_eq!(
+            format::format(&ast),
+            "a@[n] and b@<jmp>(n).\n\n@<ftref>(n){The note.}\n"
+        );
+
+        let mut ctx = render::Context::new(render::Backend::Html);
+        let h = render::html::render(&ast, &mut ctx);
+        assert_eq!(h.matches("The note.").count(), 1);
+    }
+}
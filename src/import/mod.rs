@@ -0,0 +1,8 @@
+//! Importers from other markup languages into `ast`.
+//!
+//! The result can be rendered like any parsed document, or printed as
+//! Writedown source with `format::format`.
+
+#[cfg(feature = "markdown")]
+pub mod markdown;
+pub mod review;
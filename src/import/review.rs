@@ -0,0 +1,715 @@
+use crate::ast;
+use crate::diagnostic::Diagnostic;
+use crate::format::escape_block;
+use crate::linebreak;
+use crate::parse::Options;
+use crate::token::{self, Span};
+
+/// Import a Re:VIEW chapter.
+///
+/// Headings (`==`, `=={id}`), paragraphs, `*` and `1.` lists, `#@#`
+/// comments and the blocks `//list`, `//emlist`, `//image`, `//table`,
+/// `//footnote` and `//texequation` are converted. Inline commands become
+/// functions of the same name, e.g. `@<b>{bold}` and `@<code>{x}`, which
+/// the backends render as bold and code (see `render::Markup`), except
+/// `@<fn>`, `@<href>`, `@<ruby>`, `@<m>` and the references `@<list>`,
+/// `@<img>`, `@<table>` and `@<hd>`, which have Writedown equivalents.
+///
+/// An image id is used as the figure path as is, although Re:VIEW looks
+/// it up under `images/`. Code block captions and the other blocks, such
+/// as `//note`, are reported as warnings; the contents of the latter are
+/// kept as paragraphs.
+pub fn parse(src: &str, options: &Options) -> (ast::Node, Vec<Diagnostic>) {
+    let mut im = Importer::new(options);
+    let mut lines = Lines { src, pos: 0 };
+    while let Some((pos, line)) = lines.next() {
+        im.span = Span {
+            start: pos,
+            end: pos + line.len(),
+        };
+        im.line(line, pos, &mut lines);
+    }
+    im.finish()
+}
+
+// lines and their offsets, without the line breaks
+struct Lines<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = (usize, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.src.get(self.pos..).filter(|r| !r.is_empty())?;
+        let len = rest.find('\n').unwrap_or(rest.len());
+        let pos = self.pos;
+        self.pos += len + 1;
+        Some((pos, rest[..len].trim_end_matches('\r')))
+    }
+}
+
+struct Importer<'a> {
+    options: &'a Options,
+    span: Span,
+    diagnostics: Vec<Diagnostic>,
+    // open sections, the root first
+    sections: Vec<ast::Section>,
+    // the current paragraph
+    inline: Vec<ast::ParagraphChild>,
+    // open lists, the outermost first
+    lists: Vec<ast::List>,
+}
+
+impl<'a> Importer<'a> {
+    fn new(options: &'a Options) -> Self {
+        Self {
+            options,
+            span: Span::default(),
+            diagnostics: Vec::new(),
+            sections: vec![ast::Section::new(token::Title {
+                level: 0,
+                name: String::new(),
+            })],
+            inline: Vec::new(),
+            lists: Vec::new(),
+        }
+    }
+
+    fn section(&mut self) -> &mut ast::Section {
+        self.sections.last_mut().unwrap()
+    }
+
+    fn warn(&mut self, what: &str) {
+        self.diagnostics.push(
+            Diagnostic::warning(format!("{} has no Writedown equivalent", what))
+                .with_span(self.span),
+        );
+    }
+
+    fn push_block(&mut self, node: ast::Node) {
+        self.section().child.push(node);
+    }
+
+    fn line(&mut self, line: &str, pos: usize, lines: &mut Lines) {
+        if line.trim().is_empty() {
+            return self.flush();
+        }
+        if let Some(c) = line.strip_prefix("#@#") {
+            self.flush();
+            if self.options.keep_comments {
+                let span = self.span;
+                self.push_block(ast::Node::Comment(ast::Comment {
+                    body: c.trim().to_string(),
+                    block: false,
+                    span,
+                }));
+            }
+            return;
+        }
+        if line.starts_with("#@") {
+            // `#@mapfile` and the like
+            self.flush();
+            return self.warn("a preprocessor directive");
+        }
+        if let Some((level, option, tag, title)) = heading(line) {
+            self.flush();
+            return self.heading(level, option, tag, title, pos);
+        }
+        if let Some((name, args, open)) = command(line) {
+            self.flush();
+            let body = if open {
+                self.body(name, lines)
+            } else {
+                Vec::new()
+            };
+            return self.command(name, &args, &body);
+        }
+        if let Some((depth, start, text)) = list_item(line) {
+            self.paragraph();
+            return self.item(depth, start, inline(text, pos + line.len() - text.len()));
+        }
+
+        let text = line.trim_start();
+        let pos = pos + line.len() - text.len();
+        if line.starts_with(char::is_whitespace) && !self.lists.is_empty() {
+            // continues the last item
+            let item = self.lists.last_mut().unwrap().items.last_mut().unwrap();
+            item.child.push(ast::ParagraphChild::SoftBreak);
+            item.child.extend(inline(text, pos));
+            return linebreak::join(&mut item.child, self.options.line_join);
+        }
+        self.close_lists();
+        match text.strip_prefix(": ") {
+            Some(term) if line.starts_with(char::is_whitespace) => {
+                self.warn("a definition list");
+                self.paragraph();
+                self.text_line(term, pos + 2);
+            }
+            _ => self.text_line(text, pos),
+        }
+    }
+
+    fn text_line(&mut self, line: &str, pos: usize) {
+        if line.trim().is_empty() {
+            return self.paragraph();
+        }
+        if !self.inline.is_empty() {
+            self.inline.push(ast::ParagraphChild::SoftBreak);
+        }
+        self.inline.extend(inline(line, pos));
+    }
+
+    fn heading(
+        &mut self,
+        level: usize,
+        option: Option<&str>,
+        tag: Option<String>,
+        title: &str,
+        pos: usize,
+    ) {
+        match option {
+            // the end of a column
+            Some(o) if o.starts_with('/') => return,
+            Some("column") => self.warn("a column"),
+            _ => {}
+        }
+        while self.sections.len() > 1 && self.sections.last().unwrap().level >= level {
+            self.close_section();
+        }
+        let mut s = ast::Section::new(token::Title {
+            level,
+            name: String::new(),
+        });
+        s.title = plain(&inline(title, pos));
+        s.tag = tag;
+        self.sections.push(s);
+    }
+
+    // lines up to `//}`
+    fn body<'b>(&mut self, name: &str, lines: &mut Lines<'b>) -> Vec<(usize, &'b str)> {
+        let mut body = Vec::new();
+        for (pos, l) in lines {
+            if l == "//}" {
+                return body;
+            }
+            body.push((pos, l));
+        }
+        self.diagnostics.push(
+            Diagnostic::error(format!("`//{}` is not closed by `//}}`", name)).with_span(self.span),
+        );
+        body
+    }
+
+    fn command(&mut self, name: &str, args: &[String], body: &[(usize, &str)]) {
+        let arg = |i: usize| args.get(i).filter(|a| !a.is_empty()).map(|a| a.to_string());
+        let src: String = body.iter().map(|(_, l)| format!("{}\n", l)).collect();
+
+        match name {
+            "list" | "listnum" | "emlist" | "emlistnum" | "source" | "cmd" => {
+                let (tag, caption, lang) = match name {
+                    "list" | "listnum" => (arg(0), arg(1), arg(2)),
+                    "cmd" => (None, arg(0), None),
+                    _ => (None, arg(0), arg(1)),
+                };
+                if caption.is_some() {
+                    self.warn("a code block caption");
+                }
+                self.push_block(ast::Node::Block(ast::Block::Code(ast::Code {
+                    lang,
+                    tag,
+                    src,
+                })));
+            }
+            "image" | "indepimage" => {
+                let width = arg(2).and_then(|m| {
+                    let scale = m.split(',').find_map(|m| m.trim().strip_prefix("scale="))?;
+                    Some(token::Literal::parse(scale))
+                });
+                let span = self.span;
+                self.push_block(ast::Node::Figure(ast::Figure {
+                    path: arg(0).unwrap_or_default(),
+                    alt: None,
+                    width,
+                    caption: arg(1),
+                    tag: if name == "image" { arg(0) } else { None },
+                    span,
+                }));
+            }
+            "table" | "emtable" => {
+                let (tag, caption) = match name {
+                    "table" => (arg(0), arg(1)),
+                    _ => (None, arg(0)),
+                };
+                self.push_block(ast::Node::Table(table(tag, caption, body)));
+            }
+            "footnote" => {
+                let body = args.get(1).map(|b| plain(&inline(b, self.span.start)));
+                let ft = ast::Footnote {
+                    tag: arg(0),
+                    body: body.unwrap_or_default(),
+                };
+                self.push_block(ast::Node::Paragraph(ast::Paragraph {
+                    tag: None,
+                    child: vec![ast::ParagraphChild::Footnote(ft)],
+                }));
+            }
+            "texequation" => {
+                let math = ast::Math {
+                    src: src.trim_end().to_string(),
+                    display: true,
+                };
+                self.push_block(ast::Node::Paragraph(ast::Paragraph {
+                    tag: arg(0),
+                    child: vec![ast::ParagraphChild::Math(math)],
+                }));
+            }
+            _ => {
+                self.warn(&format!("`//{}`", name));
+                for (pos, l) in body {
+                    self.text_line(l, *pos);
+                }
+                self.paragraph();
+            }
+        }
+    }
+
+    // `depth` counts from 1
+    fn item(&mut self, depth: usize, start: Option<usize>, child: Vec<ast::ParagraphChild>) {
+        // a list of the other kind
+        if depth == 1
+            && matches!(self.lists.first(), Some(l) if l.start.is_some() != start.is_some())
+        {
+            self.close_lists();
+        }
+        while self.lists.len() > depth {
+            self.close_list();
+        }
+        if self.lists.len() < depth {
+            self.lists.push(ast::List {
+                start,
+                items: Vec::new(),
+            });
+        }
+        self.lists.last_mut().unwrap().items.push(ast::ListItem {
+            check: None,
+            child,
+            list: None,
+        });
+    }
+
+    fn close_list(&mut self) {
+        let l = self.lists.pop().unwrap();
+        match self.lists.last_mut().and_then(|p| p.items.last_mut()) {
+            Some(i) => i.list = Some(l),
+            None => self.push_block(ast::Node::List(l)),
+        }
+    }
+
+    fn close_lists(&mut self) {
+        while !self.lists.is_empty() {
+            self.close_list();
+        }
+    }
+
+    fn paragraph(&mut self) {
+        if self.inline.is_empty() {
+            return;
+        }
+        let mut child = std::mem::take(&mut self.inline);
+        linebreak::join(&mut child, self.options.line_join);
+        self.push_block(ast::Node::Paragraph(ast::Paragraph { tag: None, child }));
+    }
+
+    fn flush(&mut self) {
+        self.paragraph();
+        self.close_lists();
+    }
+
+    fn close_section(&mut self) {
+        let s = self.sections.pop().unwrap();
+        self.section().child.push(ast::Node::Section(s));
+    }
+
+    fn finish(mut self) -> (ast::Node, Vec<Diagnostic>) {
+        self.flush();
+        while self.sections.len() > 1 {
+            self.close_section();
+        }
+        let root = self.sections.pop().unwrap();
+        (ast::Node::Section(root), self.diagnostics)
+    }
+}
+
+// `== title`, `=={id} title` or `==[nonum] title`
+fn heading(line: &str) -> Option<(usize, Option<&str>, Option<String>, &str)> {
+    let level = line.len() - line.trim_start_matches('=').len();
+    if level == 0 {
+        return None;
+    }
+    let mut rest = &line[level..];
+    let mut option = None;
+    if let Some(r) = rest.strip_prefix('[') {
+        let end = r.find(']')?;
+        option = Some(&r[..end]);
+        rest = &r[end + 1..];
+    }
+    let mut tag = None;
+    if let Some(r) = rest.strip_prefix('{') {
+        let end = r.find('}')?;
+        tag = Some(r[..end].to_string());
+        rest = &r[end + 1..];
+    }
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some((level, option, tag, rest.trim()))
+}
+
+// `//name[arg][arg]`, followed by `{` if it has a body
+fn command(line: &str) -> Option<(&str, Vec<String>, bool)> {
+    let s = line.strip_prefix("//")?;
+    let len = s
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(s.len());
+    if len == 0 {
+        return None;
+    }
+    let (name, mut rest) = s.split_at(len);
+
+    let mut args = Vec::new();
+    while let Some(r) = rest.strip_prefix('[') {
+        let mut arg = String::new();
+        let mut end = None;
+        let mut chars = r.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    if let Some((_, c)) = chars.next() {
+                        if c != ']' {
+                            arg.push('\\');
+                        }
+                        arg.push(c);
+                    }
+                }
+                ']' => {
+                    end = Some(i);
+                    break;
+                }
+                _ => arg.push(c),
+            }
+        }
+        rest = &r[end? + 1..];
+        args.push(arg);
+    }
+
+    match rest.trim_end() {
+        "{" => Some((name, args, true)),
+        "" => Some((name, args, false)),
+        _ => None,
+    }
+}
+
+// ` ** item` or ` 1. item`; Re:VIEW needs the leading space
+fn list_item(line: &str) -> Option<(usize, Option<usize>, &str)> {
+    let s = line.strip_prefix(char::is_whitespace)?.trim_start();
+    let stars = s.len() - s.trim_start_matches('*').len();
+    if stars > 0 {
+        let text = s[stars..].strip_prefix(char::is_whitespace)?;
+        return Some((stars, None, text.trim_start()));
+    }
+    let digits = s.len() - s.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits == 0 {
+        return None;
+    }
+    let text = s[digits..].strip_prefix(". ")?;
+    Some((1, Some(s[..digits].parse().ok()?), text.trim_start()))
+}
+
+// tab-separated cells, with the header rows above a line of `-`
+fn table(tag: Option<String>, caption: Option<String>, body: &[(usize, &str)]) -> ast::Table {
+    let mut t = ast::Table {
+        caption,
+        tag,
+        align: Vec::new(),
+        header: Vec::new(),
+        body: Vec::new(),
+    };
+    for (pos, l) in body {
+        if l.len() >= 12 && l.chars().all(|c| c == '-' || c == '=') {
+            t.header = std::mem::take(&mut t.body);
+            continue;
+        }
+        if l.trim().is_empty() {
+            continue;
+        }
+
+        let mut row = Vec::new();
+        let mut off = 0;
+        for c in l.split('\t') {
+            let cell = match c {
+                "" => None,
+                // `.` is an empty cell and `..` a leading dot
+                "." => Some(Vec::new()),
+                _ => Some(match c.strip_prefix('.') {
+                    Some(c) => inline(c, pos + off + 1),
+                    None => inline(c, pos + off),
+                }),
+            };
+            if let Some(child) = cell {
+                row.push(ast::Cell { child });
+            }
+            off += c.len() + 1;
+        }
+        t.body.push(row);
+    }
+    t
+}
+
+// `@<name>{...}` with `\}` escaped, or `@<name>$...$` and `@<name>|...|`
+// without escapes; returns the name, the contents and the length
+fn inline_command(s: &str) -> Option<(&str, String, usize)> {
+    let r = s.strip_prefix("@<")?;
+    let end = r.find('>')?;
+    let name = &r[..end];
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+
+    let r = &r[end + 1..];
+    let close = match r.chars().next()? {
+        '{' => '}',
+        '$' => '$',
+        '|' => '|',
+        _ => return None,
+    };
+    let mut content = String::new();
+    let mut chars = r.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' && close == '}' {
+            match chars.next() {
+                Some((_, '}')) => content.push('}'),
+                Some((_, c)) => {
+                    content.push('\\');
+                    content.push(c);
+                }
+                None => content.push('\\'),
+            }
+            continue;
+        }
+        if c == close {
+            return Some((name, content, 2 + end + 1 + i + 1));
+        }
+        content.push(c);
+    }
+    None
+}
+
+fn inline(mut s: &str, mut pos: usize) -> Vec<ast::ParagraphChild> {
+    let mut child = Vec::new();
+    let mut text = String::new();
+    while let Some(i) = s.find("@<") {
+        let (name, content, len) = match inline_command(&s[i..]) {
+            Some(c) => c,
+            None => {
+                text += &s[..i + 2];
+                s = &s[i + 2..];
+                pos += i + 2;
+                continue;
+            }
+        };
+        text += &s[..i];
+        if !text.is_empty() {
+            child.push(ast::ParagraphChild::Sentence(std::mem::take(&mut text)));
+        }
+        let span = Span {
+            start: pos + i,
+            end: pos + i + len,
+        };
+        child.push(inline_child(name, content, span));
+        s = &s[i + len..];
+        pos += i + len;
+    }
+    text += s;
+    if !text.is_empty() {
+        child.push(ast::ParagraphChild::Sentence(text));
+    }
+    child
+}
+
+fn inline_child(name: &str, content: String, span: Span) -> ast::ParagraphChild {
+    match name {
+        "fn" => return ast::ParagraphChild::Tag(content),
+        // `@<hd>{chapter|label}` refers to another chapter
+        "list" | "img" | "table" | "hd" | "eq" => {
+            let tag = content.rsplit('|').next().unwrap_or_default().to_string();
+            return ast::ParagraphChild::Jump(ast::Jump { tag, span });
+        }
+        "href" => {
+            let (url, label) = match content.split_once(',') {
+                Some((u, l)) => (u.trim(), Some(l.trim())),
+                None => (content.trim(), None),
+            };
+            let mut l = ast::Link::new(url);
+            l.label = label.map(|l| l.to_string());
+            l.span = span;
+            return ast::ParagraphChild::Link(l);
+        }
+        "m" => {
+            return ast::ParagraphChild::Math(ast::Math {
+                src: content,
+                display: false,
+            })
+        }
+        "ruby" => {
+            if let Some((base, text)) = content.split_once(',') {
+                return ast::ParagraphChild::Ruby(ast::Ruby::new(base.trim(), text.trim()));
+            }
+        }
+        _ => {}
+    }
+    ast::ParagraphChild::Func(ast::Func {
+        name: name.to_string(),
+        arg: Vec::new(),
+        named_arg: Vec::new(),
+        raw_arg: Vec::new(),
+        block: Some(escape_block(&content)),
+        span,
+    })
+}
+
+// text of inline content that can only hold a string
+fn plain(child: &[ast::ParagraphChild]) -> String {
+    let mut s = String::new();
+    for c in child {
+        match c {
+            ast::ParagraphChild::Sentence(t) => s += t,
+            ast::ParagraphChild::Func(f) => {
+                s += &token::unescape_text(f.block.as_deref().unwrap_or_default())
+            }
+            ast::ParagraphChild::Link(l) => s += l.text(),
+            ast::ParagraphChild::Ruby(r) => s += &r.base.concat(),
+            ast::ParagraphChild::Math(m) => s += &m.src,
+            _ => {}
+        }
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::{html, review, Backend, Context};
+    use crate::*;
+
+    #[test]
+    fn import() {
+        let re = r#"={intro} はじめに
+
+Re:VIEWは@<b>{太字}や@<code>{a\}b}を書ける@<fn>{note}。
+@<list>{hello}と@<img>{cat}を参照する。
+
+//footnote[note][脚注です]
+
+ * one
+ ** nested
+ * two
+
+//list[hello][挨拶][ruby]{
+puts "hello"
+//}
+
+//image[cat][猫][scale=0.5]{
+//}
+
+//table[t][表]{
+a	b
+------------
+1	.
+//}
+
+//note[注意]{
+本文
+//}
+"#;
+        let (ast, d) = import::review::parse(re, &Default::default());
+        assert_eq!(d.len(), 2, "{:?}", d);
+        assert!(d[0].message.contains("caption"));
+        assert!(d[1].message.contains("`//note`"));
+
+        assert_eq!(
+            format::format(&ast),
+            r#"= はじめに @[intro]
+
+Re:VIEWは@<b>{太字}や@<code>{a\}b}を書ける@[note]。@<jmp>(hello)と@<jmp>(cat)を参照する。
+
+@<ftref>(note){脚注です}
+
+- one
+  - nested
+- two
+
+@[hello]
+
+```:ruby
+puts "hello"
+```
+
+@[cat]
+
+@<img>(cat, width=0.5){猫}
+
+@[t]
+
+@<table>(表){
+| a | b |
+|---|---|
+| 1 |  |
+}
+
+本文
+"#
+        );
+
+        let mut ctx = Context::new(Backend::Review);
+        let out = review::render(&ast, &mut ctx);
+        assert_eq!(
+            out,
+            r#"={intro} はじめに
+
+Re:VIEWは@<b>{太字}や@<code>{a\}b}を書ける@<fn>{note}。@<list>{hello}と@<img>{cat}を参照する。
+
+//footnote[note][脚注です]
+
+ * one
+ ** nested
+ * two
+
+//list[hello][][ruby]{
+puts "hello"
+//}
+
+//image[cat][猫][scale=0.5]
+
+//table[t][表]{
+a	b
+------------
+1	.
+//}
+
+本文
+
+"#
+        );
+
+        // the formatted source reads back to the same Re:VIEW
+        let ast = parse(&format::format(&ast)).unwrap();
+        let mut ctx = Context::new(Backend::Review);
+        assert_eq!(review::render(&ast, &mut ctx), out);
+
+        let mut ctx = Context::new(Backend::Html);
+        let h = html::render(&ast, &mut ctx);
+        assert!(h.contains("Re:VIEWは<strong>太字</strong>や<code>a}b</code>を書ける"));
+        assert!(ctx.diagnostics.is_empty(), "{:?}", ctx.diagnostics);
+    }
+}
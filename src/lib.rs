@@ -1,9 +1,18 @@
 //#![feature(inplace_iteration)]
-#![feature(assert_matches)]
 
 pub mod ast;
+pub mod check;
+pub mod csv;
+pub mod diagnostic;
+pub mod format;
+pub mod func;
+pub mod import;
+pub mod linebreak;
+pub mod link;
 pub mod parse;
+pub mod render;
 pub mod token;
+pub mod xref;
 
 use ast::Node;
 use parse::ParseError;
@@ -14,6 +23,12 @@ pub fn parse(src: &str) -> Result<Node, ParseError> {
     parser.parse()
 }
 
+pub fn parse_with_options(src: &str, options: parse::Options) -> Result<Node, ParseError> {
+    let mut tokenizer = token::Tokenizer::new(src);
+    let parser = parse::Parser::with_options(&mut tokenizer, options);
+    parser.parse()
+}
+
 pub trait Render {
     fn render(&self) -> String;
 }
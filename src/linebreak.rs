@@ -0,0 +1,327 @@
+use crate::ast;
+
+/// How soft line breaks inside a paragraph are joined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineJoin {
+    /// Depending on the scripts on either side: nothing between East Asian
+    /// characters (e.g. Japanese or Chinese), a space otherwise. This is the
+    /// segment break transformation of CSS Text Level 3.
+    #[default]
+    Auto,
+    /// Always a single space.
+    Space,
+    /// Never a space.
+    NoSpace,
+    /// Keep `ast::ParagraphChild::SoftBreak`, e.g. for the formatter.
+    Keep,
+}
+
+/// East Asian Wide or Fullwidth, i.e. two columns on a terminal.
+pub fn is_wide(c: char) -> bool {
+    matches!(c as u32,
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xA960..=0xA97F
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE10..=0xFE19
+        | 0xFE30..=0xFE6F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x20000..=0x2FFFD
+        | 0x30000..=0x3FFFD)
+}
+
+/// East Asian Halfwidth, e.g. `ｶﾀｶﾅ`.
+pub fn is_halfwidth(c: char) -> bool {
+    matches!(c as u32, 0xFF61..=0xFFDC | 0xFFE8..=0xFFEE)
+}
+
+fn is_hangul(c: char) -> bool {
+    matches!(c as u32,
+        0x1100..=0x11FF
+        | 0x3130..=0x318F
+        | 0xA960..=0xA97F
+        | 0xAC00..=0xD7FF
+        | 0xFFA0..=0xFFDC)
+}
+
+// Korean separates words with spaces, so Hangul is not joined directly
+fn is_east_asian(c: char) -> bool {
+    (is_wide(c) || is_halfwidth(c)) && !is_hangul(c)
+}
+
+/// The text put in place of a line break between `before` and `after`.
+pub fn separator(before: Option<char>, after: Option<char>, join: LineJoin) -> &'static str {
+    match join {
+        LineJoin::Space => " ",
+        LineJoin::NoSpace | LineJoin::Keep => "",
+        LineJoin::Auto => match (before, after) {
+            (Some('\u{200B}'), _) | (_, Some('\u{200B}')) => "",
+            (Some(b), Some(a)) if is_east_asian(b) && is_east_asian(a) => "",
+            _ => " ",
+        },
+    }
+}
+
+fn first_char(c: &ast::ParagraphChild) -> Option<char> {
+    text(c).and_then(|t| t.chars().next())
+}
+
+fn last_char(c: &ast::ParagraphChild) -> Option<char> {
+    text(c).and_then(|t| t.chars().next_back())
+}
+
+// visible text; footnote marks, tags and the like are skipped
+fn text(c: &ast::ParagraphChild) -> Option<&str> {
+    match c {
+        ast::ParagraphChild::Sentence(s) => Some(s.as_str()),
+        ast::ParagraphChild::Link(l) => Some(l.text()),
+        ast::ParagraphChild::LinkRef(r) => Some(r.label.as_deref().unwrap_or(&r.name)),
+        ast::ParagraphChild::Mention(m) => Some(m.user.as_str()),
+        ast::ParagraphChild::Ruby(r) => r.base.first().map(|b| b.as_str()),
+        ast::ParagraphChild::Func(f) => f.block.as_deref(),
+        ast::ParagraphChild::Math(m) => Some(m.src.as_str()),
+        ast::ParagraphChild::Code(c) => Some(c.as_str()),
+        _ => None,
+    }
+    .filter(|t| !t.is_empty())
+}
+
+/// Columns taken by `c` on a terminal.
+pub fn char_width(c: char) -> usize {
+    if c.is_control() {
+        0
+    } else if is_wide(c) {
+        2
+    } else {
+        1
+    }
+}
+
+/// Columns taken by `s` on a terminal; ANSI escape sequences such as
+/// `\x1b[1m` take none.
+pub fn width(s: &str) -> usize {
+    let mut escape = false;
+    let mut w = 0;
+    for c in s.chars() {
+        if c == '\x1b' {
+            escape = true;
+        } else if escape {
+            escape = !c.is_ascii_alphabetic();
+        } else {
+            w += char_width(c);
+        }
+    }
+    w
+}
+
+// Japanese line breaking rules (kinsoku shori, JIS X 4051)
+/// Characters that must not begin a line: closing brackets, `、`, `。`,
+/// small kana, iteration marks and the like.
+pub const NO_LINE_START: &str = "、。，．,.:;!?)]}）］｝〕〉》」』】〙〗〟’”｠»\
+                                 ゝゞヽヾ々〻ー‐゠–〜～・：；？！‼⁇⁈⁉\
+                                 ぁぃぅぇぉっゃゅょゎゕゖァィゥェォッャュョヮヵヶ";
+/// Characters that must not end a line: opening brackets.
+pub const NO_LINE_END: &str = "([{（［｛〔〈《「『【〘〖〝‘“｟«";
+// `……` and `――` are not split
+const INSEPARABLE: &str = "…‥―";
+
+/// Whether a line may be broken between `a` and `b`.
+pub fn can_break(a: char, b: char) -> bool {
+    if NO_LINE_START.contains(b) || NO_LINE_END.contains(a) {
+        return false;
+    }
+    if a == b && INSEPARABLE.contains(a) {
+        return false;
+    }
+    if a.is_whitespace() {
+        return !b.is_whitespace();
+    }
+    // between words of Latin text only at spaces
+    let cjk = |c: char| is_wide(c) || is_halfwidth(c);
+    !b.is_whitespace() && (cjk(a) || cjk(b))
+}
+
+/// Wrap `s` to lines of at most `columns` columns, breaking at spaces and
+/// between East Asian characters while following the kinsoku rules.
+///
+/// Spaces at a break are dropped. A word longer than a line is split.
+pub fn wrap(s: &str, columns: usize) -> Vec<String> {
+    let columns = std::cmp::max(columns, 2);
+
+    // pieces that are not broken up
+    let mut units = Vec::new();
+    let mut start = 0;
+    let mut prev = None;
+    for (i, c) in s.char_indices() {
+        if prev.is_some_and(|p| can_break(p, c)) {
+            units.push(&s[start..i]);
+            start = i;
+        }
+        prev = Some(c);
+    }
+    units.push(&s[start..]);
+
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for u in units {
+        if !line.is_empty() && width(&line) + width(u.trim_end()) > columns {
+            lines.push(line.trim_end().to_string());
+            line.clear();
+        }
+        let u = if line.is_empty() { u.trim_start() } else { u };
+
+        let mut escape = false;
+        for c in u.chars() {
+            // never within an escape sequence
+            let plain = !escape && c != '\x1b';
+            if plain && width(&line) + char_width(c) > columns && !c.is_whitespace() {
+                lines.push(line.trim_end().to_string());
+                line.clear();
+            }
+            escape = c == '\x1b' || (escape && !c.is_ascii_alphabetic());
+            line.push(c);
+        }
+    }
+    if !line.trim().is_empty() {
+        lines.push(line.trim_end().to_string());
+    }
+    lines
+}
+
+/// Replace the soft breaks in `child` according to `join`, trimming the
+/// whitespace around them and merging adjacent sentences.
+pub fn join(child: &mut Vec<ast::ParagraphChild>, join: LineJoin) {
+    if join == LineJoin::Keep {
+        return;
+    }
+
+    for i in 0..child.len() {
+        if !matches!(child[i], ast::ParagraphChild::SoftBreak) {
+            continue;
+        }
+        if let Some(ast::ParagraphChild::Sentence(s)) = i.checked_sub(1).map(|i| &mut child[i]) {
+            s.truncate(s.trim_end().len());
+        }
+        if let Some(ast::ParagraphChild::Sentence(s)) = child.get_mut(i + 1) {
+            *s = s.trim_start().to_string();
+        }
+
+        let before = child[..i].iter().rev().find_map(last_char);
+        let after = child[i + 1..].iter().find_map(first_char);
+        // nothing to separate from (e.g. a line of `@<linkdef>`s), or
+        // already separated by a space before a function
+        let sep = match (before, after) {
+            (Some(b), Some(a)) if !b.is_whitespace() && !a.is_whitespace() => {
+                separator(before, after, join)
+            }
+            _ => "",
+        };
+        child[i] = ast::ParagraphChild::Sentence(sep.to_string());
+    }
+
+    let mut joined: Vec<ast::ParagraphChild> = Vec::with_capacity(child.len());
+    for c in child.drain(..) {
+        match (joined.last_mut(), c) {
+            (_, ast::ParagraphChild::Sentence(s)) if s.is_empty() => {}
+            (Some(ast::ParagraphChild::Sentence(l)), ast::ParagraphChild::Sentence(s)) => {
+                l.push_str(&s)
+            }
+            (_, c) => joined.push(c),
+        }
+    }
+    *child = joined;
+}
+
+/// Join the soft breaks of every paragraph in `root`, e.g. after parsing
+/// with `LineJoin::Keep`.
+pub fn join_lines(root: &mut ast::Node, join: LineJoin) {
+    root.walk_mut(&mut |n| {
+        if let ast::Node::Paragraph(p) = n {
+            self::join(&mut p.child, join);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::linebreak::LineJoin;
+    use crate::*;
+
+    fn paragraph(s: &str, join: LineJoin) -> Vec<ast::ParagraphChild> {
+        let opts = parse::Options {
+            line_join: join,
+            ..Default::default()
+        };
+        match parse_with_options(s, opts).unwrap() {
+            ast::Node::Section(mut s) => match s.child.remove(0) {
+                ast::Node::Paragraph(p) => p.child,
+                _ => panic!(),
+            },
+            _ => panic!(),
+        }
+    }
+
+    fn sentence(c: &[ast::ParagraphChild]) -> &str {
+        match c {
+            [ast::ParagraphChild::Sentence(s)] => s,
+            _ => panic!("{:?}", c),
+        }
+    }
+
+    #[test]
+    fn wrap() {
+        use crate::linebreak::wrap;
+
+        assert_eq!(
+            wrap("the quick brown fox jumps", 10),
+            vec!["the quick", "brown fox", "jumps"]
+        );
+        // `。` does not start a line, `「` does not end one
+        assert_eq!(
+            wrap("あいうえお。かきくけこ", 10),
+            vec!["あいうえ", "お。かきく", "けこ"]
+        );
+        assert_eq!(wrap("あいうえ「お」", 10), vec!["あいうえ", "「お」"]);
+        assert_eq!(
+            wrap("日本語とEnglishの混在", 12),
+            vec!["日本語と", "Englishの混", "在"]
+        );
+        assert_eq!(wrap("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+        // escape sequences take no columns and are not split
+        assert_eq!(
+            wrap("a \x1b[1mbold\x1b[0m word", 6),
+            vec!["a \x1b[1mbold\x1b[0m", "word"]
+        );
+        assert_eq!(
+            wrap("ab\x1b[4mcd\x1b[0m", 3),
+            vec!["ab\x1b[4mc", "d\x1b[0m"]
+        );
+    }
+
+    #[test]
+    fn join() {
+        let s = "日本語の\n文章と\nEnglish\ntext  \n  続き\n한국어\n문장\n";
+        let c = paragraph(s, LineJoin::Auto);
+        assert_eq!(sentence(&c), "日本語の文章と English text 続き 한국어 문장");
+        let c = paragraph(s, LineJoin::NoSpace);
+        assert_eq!(sentence(&c), "日本語の文章とEnglishtext続き한국어문장");
+        let c = paragraph(s, LineJoin::Space);
+        assert_eq!(
+            sentence(&c),
+            "日本語の 文章と English text 続き 한국어 문장"
+        );
+
+        let c = paragraph("脚注 @<ft>{注}\nです\n", LineJoin::Auto);
+        assert_eq!(c.len(), 3);
+        assert!(matches!(&c[2], ast::ParagraphChild::Sentence(s) if s == "です"));
+
+        let c = paragraph("a\nb\n", LineJoin::Keep);
+        assert!(matches!(c[1], ast::ParagraphChild::SoftBreak));
+    }
+}
@@ -1,6 +1,299 @@
-#[derive(Debug)]
+use std::collections::HashMap;
+
+use crate::ast;
+use crate::diagnostic::Diagnostic;
+use crate::token::Span;
+
+/// Kind of a link target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum Link {
     URL,
     Email,
     SNS,
 }
+
+/// Classify an explicit link target.
+pub fn kind_of(url: &str) -> Link {
+    if url.starts_with("mailto:") {
+        return Link::Email;
+    }
+    if !url.contains("://") && find_email(url) == Some((0, url.len())) {
+        return Link::Email;
+    }
+    Link::URL
+}
+
+const SCHEMES: [&str; 3] = ["https://", "http://", "ftp://"];
+
+/// Find the first bare URL or email address in `s`.
+///
+/// Returns the byte range and the kind of link.
+pub fn find(s: &str) -> Option<(usize, usize, Link)> {
+    let url = find_url(s);
+    let email = find_email(s);
+    match (url, email) {
+        (Some(u), Some(e)) if e.0 < u.0 => Some((e.0, e.1, Link::Email)),
+        (Some(u), _) => Some((u.0, u.1, Link::URL)),
+        (None, Some(e)) => Some((e.0, e.1, Link::Email)),
+        (None, None) => None,
+    }
+}
+
+fn find_url(s: &str) -> Option<(usize, usize)> {
+    let start = SCHEMES.iter().filter_map(|sc| s.find(sc)).min()?;
+
+    let rest = &s[start..];
+    let mut end = rest
+        .find(|c: char| !c.is_ascii_graphic() || matches!(c, '<' | '>' | '"' | '`'))
+        .unwrap_or(rest.len());
+
+    // leave trailing punctuation to the sentence
+    loop {
+        let u = &rest[..end];
+        let c = match u.chars().last() {
+            Some(c) => c,
+            None => break,
+        };
+        let unbalanced = c == ')' && u.matches('(').count() < u.matches(')').count();
+        if matches!(c, '.' | ',' | ';' | ':' | '!' | '?' | '\'') || unbalanced {
+            end -= 1;
+        } else {
+            break;
+        }
+    }
+
+    let scheme = SCHEMES.iter().find(|sc| rest.starts_with(*sc)).unwrap();
+    if end <= scheme.len() {
+        return None;
+    }
+    Some((start, start + end))
+}
+
+fn find_email(s: &str) -> Option<(usize, usize)> {
+    let local = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '%' | '+' | '-');
+    let domain = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '-');
+
+    for (at, _) in s.match_indices('@') {
+        let start = s[..at]
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| !local(c))
+            .map(|(i, c)| i + c.len_utf8())
+            .unwrap_or(0);
+        let end = s[at + 1..]
+            .find(|c: char| !domain(c))
+            .map(|i| at + 1 + i)
+            .unwrap_or(s.len());
+        let d = s[at + 1..end].trim_end_matches(['.', '-']);
+        let end = at + 1 + d.len();
+
+        // part of a URL, e.g. https://mstdn.maud.io/@sksat
+        if s[..start].ends_with('/') {
+            continue;
+        }
+        if start < at && d.contains('.') && !d.starts_with('.') {
+            return Some((start, end));
+        }
+    }
+    None
+}
+
+/// Whether `url` starts with a scheme such as `https:` or `mailto:`.
+pub fn has_scheme(url: &str) -> bool {
+    match url.split_once(':') {
+        Some((s, _)) => {
+            let mut c = s.chars();
+            c.next().is_some_and(|c| c.is_ascii_alphabetic())
+                && c.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '.' | '-'))
+        }
+        None => false,
+    }
+}
+
+/// Check that an absolute URL is well-formed, without touching the network.
+pub fn validate_url(url: &str) -> Result<(), String> {
+    if url.contains(char::is_whitespace) {
+        return Err("contains whitespace".to_string());
+    }
+    if !has_scheme(url) {
+        return Err("missing scheme".to_string());
+    }
+    if let Some(addr) = url.strip_prefix("mailto:") {
+        return match find_email(addr) {
+            Some((0, e)) if e == addr.len() => Ok(()),
+            _ => Err(format!("invalid email address `{}`", addr)),
+        };
+    }
+    let rest = match url.split_once("://") {
+        Some((_, r)) => r,
+        // e.g. `tel:` or `urn:`
+        None => return Ok(()),
+    };
+
+    let authority = rest.split(['/', '?', '#']).next().unwrap();
+    let host = authority.rsplit('@').next().unwrap();
+    let host = match host.rsplit_once(':') {
+        Some((h, port)) if !h.ends_with(']') || host.starts_with('[') => {
+            if !port.chars().all(|c| c.is_ascii_digit()) {
+                return Err(format!("invalid port `{}`", port));
+            }
+            h
+        }
+        _ => host,
+    };
+    if host.is_empty() {
+        return Err("missing host".to_string());
+    }
+    if host.starts_with('[') && host.ends_with(']') {
+        return Ok(());
+    }
+    let valid = host
+        .split('.')
+        .all(|l| !l.is_empty() && l.chars().all(|c| c.is_alphanumeric() || c == '-'));
+    if !valid {
+        return Err(format!("invalid host `{}`", host));
+    }
+    Ok(())
+}
+
+/// Replace every `@<linkref>` with the `@<linkdef>` of the same name.
+///
+/// Definitions are removed from the tree, along with paragraphs left
+/// empty. Undefined, unused and redefined names are reported.
+pub fn resolve(root: &mut ast::Node) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    let mut defs: HashMap<String, ast::Link> = HashMap::new();
+    let mut order: Vec<(String, Span)> = Vec::new();
+    root.walk(&mut |n| {
+        for c in n.inline().into_iter().flatten() {
+            if let ast::ParagraphChild::LinkDef(d) = c {
+                if defs.contains_key(&d.name) {
+                    diagnostics.push(
+                        Diagnostic::error(format!("link `{}` is defined more than once", d.name))
+                            .with_span(d.link.span),
+                    );
+                    continue;
+                }
+                defs.insert(d.name.clone(), d.link.clone());
+                order.push((d.name.clone(), d.link.span));
+            }
+        }
+    });
+
+    let mut used = Vec::new();
+    root.walk_mut(&mut |n| {
+        let s = match n {
+            ast::Node::Section(s) => s,
+            _ => return,
+        };
+        for child in s.child.iter_mut().flat_map(|c| c.inline_mut()) {
+            child.retain(|c| !matches!(c, ast::ParagraphChild::LinkDef(_)));
+            for c in child.iter_mut() {
+                let r = match c {
+                    ast::ParagraphChild::LinkRef(r) => r,
+                    _ => continue,
+                };
+                match defs.get(&r.name) {
+                    Some(l) => {
+                        let mut l = l.clone();
+                        l.label = r.label.take();
+                        used.push(r.name.clone());
+                        *c = ast::ParagraphChild::Link(l);
+                    }
+                    None => diagnostics.push(
+                        Diagnostic::error(format!("link `{}` is not defined", r.name))
+                            .with_span(r.span),
+                    ),
+                }
+            }
+        }
+        s.child
+            .retain(|c| !matches!(c, ast::Node::Paragraph(p) if p.child.is_empty()));
+    });
+
+    for (name, span) in order {
+        if !used.contains(&name) {
+            diagnostics.push(
+                Diagnostic::warning(format!("link `{}` is defined but never used", name))
+                    .with_span(span),
+            );
+        }
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::link::{find, Link};
+    use crate::*;
+
+    #[test]
+    fn resolve() {
+        let s = r#"@<linkdef>(rust, https://www.rust-lang.org/, title=Rust)
+@<linkdef>(unused, https://example.com/)
+
+see @<linkref>(rust){the Rust site} and @<linkref>(rust).
+@<linkref>(nothing){oops}
+"#;
+        let mut ast = parse(s).unwrap();
+        let diag = link::resolve(&mut ast);
+        assert_eq!(diag.len(), 2);
+        assert!(diag[0].message.contains("nothing"));
+        assert!(diag[1].message.contains("unused"));
+
+        let s = match ast {
+            ast::Node::Section(s) => s,
+            _ => panic!(),
+        };
+        assert_eq!(s.child.len(), 1);
+        let p = match &s.child[0] {
+            ast::Node::Paragraph(p) => p,
+            _ => panic!(),
+        };
+        let l = match &p.child[1] {
+            ast::ParagraphChild::Link(l) => l,
+            _ => panic!(),
+        };
+        assert_eq!(l.url, "https://www.rust-lang.org/");
+        assert_eq!(l.text(), "the Rust site");
+        assert_eq!(l.title.as_deref(), Some("Rust"));
+        assert!(matches!(&p.child[3], ast::ParagraphChild::Link(l) if l.label.is_none()));
+    }
+
+    #[test]
+    fn autolink() {
+        let s = "email test: sksat@sksat.net.";
+        let (b, e, k) = find(s).unwrap();
+        assert_eq!((&s[b..e], k), ("sksat@sksat.net", Link::Email));
+
+        let s = "連絡先はsksat@sksat.net";
+        let (b, e, k) = find(s).unwrap();
+        assert_eq!((&s[b..e], k), ("sksat@sksat.net", Link::Email));
+
+        let s = "リンク(https://example.com/a_(b))です";
+        let (b, e, k) = find(s).unwrap();
+        assert_eq!((&s[b..e], k), ("https://example.com/a_(b)", Link::URL));
+
+        assert_eq!(
+            find("https://mstdn.maud.io/@sksat"),
+            Some((0, 28, Link::URL))
+        );
+        assert_eq!(find("a@b, @c"), None);
+    }
+
+    #[test]
+    fn validate() {
+        use crate::link::validate_url;
+        assert!(validate_url("https://sksat.net/").is_ok());
+        assert!(validate_url("http://localhost:8080/a?b#c").is_ok());
+        assert!(validate_url("mailto:sksat@sksat.net").is_ok());
+        assert!(validate_url("https://").is_err());
+        assert!(validate_url("https://a..b/").is_err());
+        assert!(validate_url("https://host:port/").is_err());
+        assert!(validate_url("mailto:sksat").is_err());
+    }
+}
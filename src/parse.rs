@@ -1,15 +1,50 @@
+use std::fmt;
+
 use crate::ast;
+use crate::csv;
+use crate::diagnostic::Diagnostic;
+use crate::linebreak;
+use crate::link;
 use crate::token;
 
 use token::TokenKind;
 use token::Tokenizer;
 
-#[derive(Debug)]
-pub enum ParseError {}
+/// Malformed input the parser cannot recover from, e.g. an unterminated
+/// code block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: token::Span,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<ParseError> for Diagnostic {
+    fn from(e: ParseError) -> Self {
+        Diagnostic::error(e.message).with_span(e.span)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Keep comments as `ast::Node::Comment` instead of dropping them.
+    ///
+    /// A comment line inside a paragraph then ends the paragraph; when
+    /// comments are dropped the paragraph continues after it.
+    pub keep_comments: bool,
+    /// How lines of a paragraph are joined.
+    pub line_join: linebreak::LineJoin,
+}
 
 pub struct Parser<'a> {
     tokenizer: &'a mut Tokenizer<'a>,
     pub root: ast::Node,
+    pub options: Options,
 }
 
 impl<'a> Parser<'a> {
@@ -20,26 +55,41 @@ impl<'a> Parser<'a> {
                 level: 0,
                 name: "".to_string(),
             })),
+            options: Options::default(),
+        }
+    }
+
+    pub fn with_options(tokenizer: &'a mut Tokenizer<'a>, options: Options) -> Parser<'a> {
+        Self {
+            options,
+            ..Self::new(tokenizer)
         }
     }
 
     pub fn parse(mut self) -> Result<ast::Node, ParseError> {
         let tok = &mut self.tokenizer;
 
-        match &mut self.root {
-            ast::Node::Section(ref mut sec) => {
-                let _ = parse_section(tok, sec).unwrap();
-            }
-            _ => {}
+        if let ast::Node::Section(ref mut sec) = &mut self.root {
+            parse_section(tok, sec, &self.options).unwrap();
         }
 
-        Ok(self.root)
+        match self.tokenizer.take_error() {
+            Some(e) => Err(e),
+            None => Ok(self.root),
+        }
     }
 }
 
-fn parse_section(tok: &mut Tokenizer, section: &mut ast::Section) -> Result<(), ()> {
+fn parse_section(
+    tok: &mut Tokenizer,
+    section: &mut ast::Section,
+    opts: &Options,
+) -> Result<(), ()> {
     //println!("parse_section");
     //assert_eq!(section, ast::Node::Section);
+    // a `@[tag]` on its own attaches to the next block
+    let mut pending_tag: Option<ast::Paragraph> = None;
+
     loop {
         //let l = &tok.before;
         let t = tok.peek();
@@ -50,38 +100,567 @@ fn parse_section(tok: &mut Tokenizer, section: &mut ast::Section) -> Result<(),
 
         match t.kind {
             TokenKind::Title(title) => {
+                if title.level <= section.level {
+                    // sibling or parent section
+                    break;
+                }
+                if let Some(p) = pending_tag.take() {
+                    section.child.push(ast::Node::Paragraph(p));
+                }
+
                 // child section
                 tok.next();
                 //let t = tok.peek().unwrap();
                 //if t.kind == TokenKind::Newline {
                 let mut sec = ast::Section::new(title);
-                let _ = parse_section(tok, &mut sec).unwrap();
+                parse_section(tok, &mut sec, opts).unwrap();
                 section.child.push(ast::Node::Section(sec));
             }
-            TokenKind::Comment | TokenKind::Newline => {
+            TokenKind::Header => {
+                tok.next();
+                let h = parse_header(tok.get_str(&t));
+                section.child.push(ast::Node::Header(h));
+            }
+            TokenKind::Comment => {
+                let _ = tok.next();
+                if opts.keep_comments {
+                    let c = ast::Comment::new(tok.get_str(&t), t.span());
+                    section.child.push(ast::Node::Comment(c));
+                }
+            }
+            TokenKind::Newline => {
                 let _ = tok.next();
             }
             TokenKind::Unknown => {
                 panic!("unknown token")
             }
-            TokenKind::CodeBlock(_) => {
-                let blk = ast::Block::Code(tok.get_str(&t).to_string());
-                section.child.push(ast::Node::Block(blk));
+            TokenKind::TableRow => {
+                let mut rows = Vec::new();
+                while let Some(t) = tok.peek() {
+                    match t.kind {
+                        TokenKind::TableRow => {
+                            rows.push((t.span().start, tok.get_str(&t).to_string()))
+                        }
+                        // a single newline ends the row, a blank line the table
+                        TokenKind::Newline
+                            if tok.now().map(|t| t.kind) != Some(TokenKind::Newline) => {}
+                        _ => break,
+                    }
+                    tok.next();
+                }
+                let mut table = get_table(&rows, opts);
+                table.tag = pending_tag.take().and_then(|p| p.tag_only());
+                section.child.push(ast::Node::Table(table));
+            }
+            TokenKind::ListItem => {
+                if let Some(p) = pending_tag.take() {
+                    section.child.push(ast::Node::Paragraph(p));
+                }
+                section.child.push(ast::Node::List(get_list(tok, opts)));
+            }
+            TokenKind::CodeBlock(ref lang) => {
+                let code = ast::Code {
+                    lang: lang.clone(),
+                    tag: pending_tag.take().and_then(|p| p.tag_only()),
+                    src: tok.get_str(&t).to_string(),
+                };
+                section.child.push(ast::Node::Block(ast::Block::Code(code)));
                 let _ = tok.next();
             }
             _ => {
-                let p = get_paragraph(tok);
-                if let Some(p) = p {
-                    section.child.push(ast::Node::Paragraph(p));
+                let p = get_paragraph(tok, opts);
+                if let Some(mut p) = p {
+                    if p.tag_only().is_some() && pending_tag.is_none() {
+                        pending_tag = Some(p);
+                        continue;
+                    }
+                    p.tag = pending_tag.take().and_then(|t| t.tag_only());
+                    match get_block(&mut p, opts) {
+                        Ok(Some(n)) => section.child.push(n),
+                        Ok(None) => section.child.push(ast::Node::Paragraph(p)),
+                        Err(e) => {
+                            tok.set_error(e);
+                            break;
+                        }
+                    }
                 }
             }
         }
     }
+    if let Some(p) = pending_tag {
+        section.child.push(ast::Node::Paragraph(p));
+    }
 
     Ok(())
 }
 
-fn get_paragraph(tok: &mut Tokenizer) -> Option<ast::Paragraph> {
+/// Consecutive list items, nested by indentation. The text of an item runs
+/// up to the next item or a blank line.
+fn get_list(tok: &mut Tokenizer, opts: &Options) -> ast::List {
+    // open lists with their indentation, innermost last
+    let mut stack: Vec<(usize, ast::List)> = Vec::new();
+
+    fn close(stack: &mut Vec<(usize, ast::List)>) {
+        let (_, l) = stack.pop().unwrap();
+        let parent = stack.last_mut().unwrap().1.items.last_mut().unwrap();
+        parent.list = Some(l);
+    }
+
+    while let Some(t) = tok.peek() {
+        match t.kind {
+            TokenKind::ListItem => {}
+            // a blank line between items
+            TokenKind::Newline => {
+                tok.next();
+                continue;
+            }
+            _ => break,
+        }
+        let marker = tok.get_str(&t);
+
+        let body = marker.trim_start();
+        let indent: usize = marker[..marker.len() - body.len()]
+            .chars()
+            .map(|c| if c == '\t' { 4 } else { 1 })
+            .sum();
+        let (number, check) = body
+            .trim_end()
+            .split_once(' ')
+            .unwrap_or((body.trim_end(), ""));
+        let start = number.strip_suffix('.').and_then(|n| n.parse().ok());
+        let check = match check {
+            "[ ]" => Some(false),
+            "[x]" | "[X]" => Some(true),
+            _ => None,
+        };
+
+        // less indented than the first item is still top level
+        let indent = std::cmp::max(indent, stack.first().map(|s| s.0).unwrap_or(0));
+        // a bullet after a numbered item (or vice versa) starts a new list
+        let top = stack.first().filter(|s| s.0 == indent);
+        if top.is_some_and(|(_, l)| l.start.is_some() != start.is_some()) {
+            break;
+        }
+        tok.next();
+
+        let item = ast::ListItem {
+            check,
+            child: get_paragraph(tok, opts)
+                .map(|p| p.child)
+                .unwrap_or_default(),
+            list: None,
+        };
+
+        while stack.len() > 1 && stack.last().unwrap().0 > indent {
+            close(&mut stack);
+        }
+        match stack.last_mut() {
+            Some((i, l)) if *i >= indent => l.items.push(item),
+            _ => stack.push((
+                indent,
+                ast::List {
+                    start,
+                    items: vec![item],
+                },
+            )),
+        }
+    }
+
+    while stack.len() > 1 {
+        close(&mut stack);
+    }
+    stack.pop().map(|s| s.1).unwrap()
+}
+
+/// A paragraph of a single block function becomes that block:
+/// `@<img>(path, alt=..., width=...){caption}` a figure and
+/// `@<table>(caption){rows}` a table.
+///
+/// Returns `None` if `p` is not such a block, and an error if it is a block
+/// that cannot be built, e.g. invalid CSV.
+pub(crate) fn get_block(
+    p: &mut ast::Paragraph,
+    opts: &Options,
+) -> Result<Option<ast::Node>, ParseError> {
+    let f = match p.child.as_slice() {
+        [ast::ParagraphChild::Func(f)] => f,
+        _ => return Ok(None),
+    };
+
+    let node = match f.name.as_str() {
+        "img" if f.get_arg(0).is_some() => ast::Node::Figure(ast::Figure {
+            path: f.get_arg_str(0).unwrap(),
+            alt: f.get_named_arg_str("alt"),
+            width: f.get_named_arg("width").cloned(),
+            caption: f.block.as_deref().map(token::unescape_text),
+            tag: p.tag.take(),
+            span: f.span,
+        }),
+        "table" => {
+            let block = f.block.as_deref().unwrap_or_default();
+            // the block ends before the closing `}`
+            let mut pos = f.span.end.saturating_sub(block.len() + 1);
+            let mut rows = Vec::new();
+            for l in block.split_inclusive('\n') {
+                let row = l.trim_start();
+                if !row.trim_end().is_empty() {
+                    rows.push((pos + l.len() - row.len(), row.trim_end()));
+                }
+                pos += l.len();
+            }
+            let mut table = get_table(&rows, opts);
+            table.caption = f.get_arg_str(0).or_else(|| f.get_named_arg_str("caption"));
+            table.tag = p.tag.take();
+            ast::Node::Table(table)
+        }
+        // tables from files are loaded by `csv::load`
+        "csvtable" if f.get_arg(0).is_none() => {
+            let block = f.block.as_deref().unwrap_or_default();
+            let mut table = csv::table_from_func(f, block).map_err(|e| ParseError {
+                message: format!("invalid table: {}", e),
+                span: f.span,
+            })?;
+            table.tag = p.tag.take();
+            ast::Node::Table(table)
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(node))
+}
+
+/// Build a table from `| a | b |` rows and their positions in the document.
+///
+/// Rows before a `|---|:--:|` separator are header rows, and the colons in
+/// the separator set the column alignment.
+fn get_table<S: AsRef<str>>(rows: &[(usize, S)], opts: &Options) -> ast::Table {
+    let mut table = ast::Table {
+        caption: None,
+        tag: None,
+        align: Vec::new(),
+        header: Vec::new(),
+        body: Vec::new(),
+    };
+
+    for (pos, r) in rows {
+        let cells = split_row(r.as_ref());
+        let sep = cells.iter().all(|(_, c)| {
+            let c = c.trim_start_matches(':').trim_end_matches(':');
+            !c.is_empty() && c.chars().all(|c| c == '-')
+        });
+        if sep && table.align.is_empty() {
+            table.align = cells
+                .iter()
+                .map(|(_, c)| match (c.starts_with(':'), c.ends_with(':')) {
+                    (true, true) => ast::Align::Center,
+                    (true, false) => ast::Align::Left,
+                    (false, true) => ast::Align::Right,
+                    (false, false) => ast::Align::Default,
+                })
+                .collect();
+            table.header = std::mem::take(&mut table.body);
+            continue;
+        }
+
+        let row = cells
+            .iter()
+            .map(|(i, c)| ast::Cell {
+                child: get_inline(c, pos + i, opts),
+            })
+            .collect();
+        table.body.push(row);
+    }
+
+    table
+}
+
+// cells of a row with their offsets in it, as written; `\|` is resolved
+// by the inline parser like any other escape
+fn split_row(r: &str) -> Vec<(usize, &str)> {
+    let mut cells = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in r.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '|' => {
+                cells.push((start, &r[start..i]));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    cells.push((start, &r[start..]));
+
+    // the outer `|`s are optional
+    if cells.len() > 1 && cells[0].1.trim().is_empty() {
+        cells.remove(0);
+    }
+    if cells.len() > 1 && cells.last().unwrap().1.trim().is_empty() {
+        cells.pop();
+    }
+    cells
+        .into_iter()
+        .map(|(i, c)| {
+            let t = c.trim_start();
+            (i + c.len() - t.len(), t.trim_end())
+        })
+        .collect()
+}
+
+/// Parse inline content such as a table cell found at `pos` in the
+/// document.
+fn get_inline(s: &str, pos: usize, opts: &Options) -> Vec<ast::ParagraphChild> {
+    let mut tok = Tokenizer::inline(s);
+    let mut child = Vec::new();
+    while tok.peek().is_some() {
+        if let Some(mut p) = get_paragraph(&mut tok, opts) {
+            child.append(&mut p.child);
+        }
+    }
+
+    for c in &mut child {
+        let span = match c {
+            ast::ParagraphChild::Func(f) => &mut f.span,
+            ast::ParagraphChild::Jump(j) => &mut j.span,
+            ast::ParagraphChild::Link(l) => &mut l.span,
+            ast::ParagraphChild::LinkDef(d) => &mut d.link.span,
+            ast::ParagraphChild::LinkRef(r) => &mut r.span,
+            _ => continue,
+        };
+        span.start += pos;
+        span.end += pos;
+    }
+    child
+}
+
+pub(crate) fn parse_header(s: &str) -> ast::Header {
+    let mut header = ast::Header {
+        doctype: None,
+        title: None,
+        author: None,
+        description: None,
+        fields: Vec::new(),
+    };
+
+    // strip the `---` fences
+    let mut lines: Vec<&str> = s.lines().skip(1).collect();
+    lines.pop();
+
+    for l in lines {
+        let (key, value) = match l.split_once(':') {
+            Some(kv) => kv,
+            None => continue,
+        };
+        let key = key.trim().to_string();
+        let value = token::Literal::parse(value);
+
+        let s = value.as_str().map(|s| s.to_string());
+        match key.as_str() {
+            "title" => header.title = s,
+            "author" => header.author = s,
+            "description" => header.description = s,
+            _ => {}
+        }
+        header.fields.push((key, value));
+    }
+
+    header
+}
+
+fn get_func(tok: &mut Tokenizer) -> ast::Func {
+    let t = tok.next().unwrap();
+    assert_eq!(t.kind, TokenKind::Func);
+    let name = tok.get_str(&t).to_string();
+    // `@<` and `>`
+    let mut span = t.span();
+    span.start -= 2;
+    span.end += 1;
+
+    let mut arg = Vec::new();
+    let mut named_arg = Vec::new();
+    let mut raw_arg = Vec::new();
+
+    // `(arg)` may be omitted: `@<ft>{block}`
+    let t = tok.peek();
+    let t = if t.map(|t| t.kind) == Some(TokenKind::FuncArgOpen) {
+        let _ = tok.next().unwrap();
+        tok.peek()
+    } else {
+        None
+    };
+    let t = match t {
+        Some(t) => t.kind,
+        None => TokenKind::Unknown,
+    };
+
+    if t == TokenKind::FuncArgClose {
+        // no arg
+        let t = tok.next().unwrap();
+        span.end = t.span().end;
+    } else if t == TokenKind::FuncArg {
+        // get arg
+        // the tokenizer stops with an error at unterminated arguments
+        while let Some(t) = tok.peek() {
+            match t.kind {
+                TokenKind::FuncArg => {
+                    let t = tok.next().unwrap();
+                    let a = tok.get_str(&t);
+                    let (key, value, raw) = get_func_arg(a);
+                    match &key {
+                        Some(k) => named_arg.push((k.clone(), value)),
+                        None => arg.push(value),
+                    }
+                    raw_arg.push((key, raw));
+                }
+                TokenKind::FuncArgClose => {
+                    let t = tok.next().unwrap();
+                    span.end = t.span().end;
+                    break;
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+    //dbg!(&arg);
+
+    let t = tok.peek();
+    let block = if t.map(|t| t.kind) == Some(TokenKind::FuncBlock) {
+        // get block
+        let t = tok.next().unwrap();
+        let s = tok.get_str(&t).to_string();
+        // `}`
+        span.end = t.span().end + 1;
+        Some(s)
+    } else {
+        None
+    };
+
+    ast::Func {
+        name,
+        arg,
+        named_arg,
+        raw_arg,
+        block,
+        span,
+    }
+}
+
+/// Push a sentence, splitting out bare URLs and email addresses.
+fn push_sentence(child: &mut Vec<ast::ParagraphChild>, mut s: &str, mut pos: usize) {
+    while let Some((b, e, kind)) = link::find(s) {
+        if b != 0 {
+            child.push(ast::ParagraphChild::Sentence(token::unescape_text(&s[..b])));
+        }
+        let mut l = ast::Link::new(&s[b..e]);
+        l.kind = kind;
+        l.span = token::Span {
+            start: pos + b,
+            end: pos + e,
+        };
+        child.push(ast::ParagraphChild::Link(l));
+        s = &s[e..];
+        pos += e;
+    }
+    if !s.is_empty() {
+        child.push(ast::ParagraphChild::Sentence(token::unescape_text(s)));
+    }
+}
+
+/// Turn calls of built-in functions into their dedicated nodes.
+///
+/// `Func::block` is kept as written; blocks that are plain text (footnote
+/// bodies, labels, captions) have their escapes resolved here.
+fn get_builtin(f: ast::Func) -> ast::ParagraphChild {
+    let text = f.block.as_deref().map(token::unescape_text);
+    match f.name.as_str() {
+        "ft" => ast::ParagraphChild::Footnote(ast::Footnote {
+            tag: None,
+            body: text.unwrap_or_default(),
+        }),
+        "ftref" => ast::ParagraphChild::Footnote(ast::Footnote {
+            tag: Some(f.get_arg_str(0).unwrap_or_default()),
+            body: text.unwrap_or_default(),
+        }),
+        "link" => match f.get_arg_str(0) {
+            Some(url) => {
+                let mut l = ast::Link::new(&url);
+                l.span = f.span;
+                l.label = text;
+                l.title = f.get_named_arg_str("title");
+                ast::ParagraphChild::Link(l)
+            }
+            None => ast::ParagraphChild::Func(f),
+        },
+        "linkdef" => match (f.get_arg_str(0), f.get_arg_str(1)) {
+            (Some(name), Some(url)) => {
+                let mut link = ast::Link::new(&url);
+                link.span = f.span;
+                link.title = f.get_named_arg_str("title");
+                ast::ParagraphChild::LinkDef(ast::LinkDef { name, link })
+            }
+            _ => ast::ParagraphChild::Func(f),
+        },
+        "linkref" => match f.get_arg_str(0) {
+            Some(name) => ast::ParagraphChild::LinkRef(ast::LinkRef {
+                name,
+                label: text,
+                span: f.span,
+            }),
+            None => ast::ParagraphChild::Func(f),
+        },
+        "jmp" => match f.get_arg_str(0) {
+            Some(tag) => ast::ParagraphChild::Jump(ast::Jump { tag, span: f.span }),
+            None => ast::ParagraphChild::Func(f),
+        },
+        "ruby" => match (f.get_arg_str(0), f.get_arg_str(1)) {
+            (Some(base), Some(text)) => ast::ParagraphChild::Ruby(ast::Ruby::new(&base, &text)),
+            _ => ast::ParagraphChild::Func(f),
+        },
+        _ => ast::ParagraphChild::Func(f),
+    }
+}
+
+/// Split a raw function argument into an optional `key=`, its value and
+/// the value as a string.
+///
+/// Quoted values are always strings, and so are values containing
+/// backslash escapes (`a\,b`); everything else goes through
+/// `Literal::parse`.
+fn get_func_arg(s: &str) -> (Option<String>, token::Literal, String) {
+    let mut key = None;
+    let mut value = s.trim();
+
+    if let Some((k, v)) = value.split_once('=') {
+        let k = k.trim();
+        let mut c = k.chars();
+        let ident = c.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+            && c.all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+        if ident {
+            key = Some(k.to_string());
+            value = v.trim();
+        }
+    }
+
+    let quoted = value.len() >= 2 && value.starts_with('"') && value.ends_with('"');
+    let raw = if quoted {
+        token::unescape(&value[1..value.len() - 1])
+    } else {
+        token::unescape(value)
+    };
+    let value = if !quoted && value.contains('\\') {
+        token::Literal::Str(raw.clone())
+    } else {
+        token::Literal::parse(value)
+    };
+
+    (key, value, raw)
+}
+
+fn get_paragraph(tok: &mut Tokenizer, opts: &Options) -> Option<ast::Paragraph> {
     let mut child = Vec::new();
 
     loop {
@@ -102,64 +681,47 @@ fn get_paragraph(tok: &mut Tokenizer) -> Option<ast::Paragraph> {
                     break;
                 }
                 let _ = tok.next();
+                if !child.is_empty()
+                    && !matches!(child.last(), Some(ast::ParagraphChild::SoftBreak))
+                {
+                    child.push(ast::ParagraphChild::SoftBreak);
+                }
             }
             TokenKind::Sentence => {
                 let t = tok.next().unwrap();
                 let s = tok.get_str(&t);
-                child.push(ast::ParagraphChild::Sentence(s.to_string()));
+                push_sentence(&mut child, s, t.span().start);
             }
             TokenKind::Func => {
+                let f = get_func(tok);
+                child.push(get_builtin(f));
+            }
+            TokenKind::Tag => {
                 let t = tok.next().unwrap();
-                assert_eq!(t.kind, TokenKind::Func);
-                let name = tok.get_str(&t).to_string();
-
+                let s = tok.get_str(&t);
+                child.push(ast::ParagraphChild::Tag(s.to_string()));
+            }
+            TokenKind::AtString => {
                 let t = tok.next().unwrap();
-                assert_eq!(t.kind, TokenKind::FuncArgOpen);
-
-                let mut arg = Vec::new();
-
-                let t = tok.peek().unwrap();
-                //dbg!(&t.kind);
-
-                if t.kind == TokenKind::FuncArgClose {
-                    // no arg
-                    let _ = tok.next().unwrap();
-                } else if t.kind == TokenKind::FuncArg {
-                    // get arg
-                    loop {
-                        let t = tok.peek();
-                        let t = t.unwrap();
-                        //dbg!(&t);
-                        match t.kind {
-                            TokenKind::FuncArg => {
-                                let t = tok.next().unwrap();
-                                let a = tok.get_str(&t);
-                                arg.push(a.to_string());
-                            }
-                            TokenKind::FuncArgClose => {
-                                let _ = tok.next().unwrap();
-                                break;
-                            }
-                            _ => unreachable!(),
-                        }
-                    }
-                }
-                //dbg!(&arg);
-                let arg = if arg.len() == 0 { None } else { Some(arg) };
-
-                let t = tok.peek().unwrap();
-                let block = if t.kind == TokenKind::FuncBlock {
-                    // get block
-                    let t = tok.next().unwrap();
-                    let s = tok.get_str(&t).to_string();
-                    Some(s)
-                } else {
-                    None
-                };
-
-                child.push(ast::ParagraphChild::Func(ast::Func { name, arg, block }))
+                let s = tok.get_str(&t);
+                child.push(ast::ParagraphChild::Mention(ast::Mention::new(s)));
+            }
+            TokenKind::Math => {
+                let t = tok.next().unwrap();
+                child.push(ast::ParagraphChild::Math(ast::Math::new(tok.get_str(&t))));
+            }
+            TokenKind::InlineCode => {
+                let t = tok.next().unwrap();
+                child.push(ast::ParagraphChild::Code(tok.get_str(&t).to_string()));
+            }
+            TokenKind::Comment if !opts.keep_comments => {
+                tok.next();
             }
-            TokenKind::Title(_) | TokenKind::CodeBlock(_) => break,
+            TokenKind::Title(_)
+            | TokenKind::CodeBlock(_)
+            | TokenKind::TableRow
+            | TokenKind::ListItem
+            | TokenKind::Comment => break,
             _ => {
                 //eprintln!("get_paragraph: {:?}", &t.kind);
                 tok.next();
@@ -168,16 +730,48 @@ fn get_paragraph(tok: &mut Tokenizer) -> Option<ast::Paragraph> {
         }
     }
 
+    while matches!(child.last(), Some(ast::ParagraphChild::SoftBreak)) {
+        child.pop();
+    }
     if child.is_empty() {
         return None;
     }
-    Some(ast::Paragraph { child })
+    linebreak::join(&mut child, opts.line_join);
+    Some(ast::Paragraph { tag: None, child })
 }
 
 #[cfg(test)]
 mod tests {
     use crate::*;
 
+    #[test]
+    fn error() {
+        for (s, msg, start) in [
+            ("a\n`code", "no end mark of inline code", 2),
+            ("``x``\n", "a code block starts with three backquotes", 0),
+            ("```\nfn main() {}\n", "no end mark of code block", 0),
+            ("```:rust", "no end mark of code block", 0),
+            ("```rust\n```\n", "a code block starts with a newline", 0),
+            ("@<f>(a, b", "no end mark of function arguments", 8),
+            (
+                "@<ft>{note\n\n= Next\n",
+                "no end mark `}` of function block",
+                5,
+            ),
+            ("a\n\n#[ never closed\n", "no end mark `]#` of comment", 3),
+        ] {
+            let e = parse(s).unwrap_err();
+            assert!(e.message.starts_with(msg), "{:?}: {}", s, e);
+            assert_eq!(e.span.start, start);
+        }
+        assert!(parse("= \n").is_ok());
+        // an unclosed function or tag is text
+        let ast = parse("a@<b c\n").unwrap();
+        assert_eq!(format::format(&ast), "a\\@<b c\n");
+        let ast = parse("a @[b\n").unwrap();
+        assert_eq!(format::format(&ast), "a \\@[b\n");
+    }
+
     #[test]
     fn simple() {
         let s = r#"sentence0
@@ -205,7 +799,7 @@ p1s1
 
         dbg!(&ast);
 
-        assert_matches!(&ast, ast::Node::Section(_));
+        assert!(matches!(&ast, ast::Node::Section(_)));
         let s = match ast {
             ast::Node::Section(s) => s,
             _ => panic!(""),
@@ -239,8 +833,9 @@ p1s1
             _ => panic!(""),
         };
 
-        assert_eq!(s10.child.len(), 4);
-        assert_eq!(s11.child.len(), 2);
+        // lines are joined into one sentence
+        assert_eq!(s10.child.len(), 1);
+        assert_eq!(s11.child.len(), 1);
     }
 
     #[test]
@@ -263,4 +858,241 @@ p1s1
 
         dbg!(&ast);
     }
+
+    #[test]
+    fn func_arg() {
+        let s = r#"@<f>(fig.png, width=0.8, alt="a (b), c", a\,b\)){caption}
+"#;
+        let ast = parse(s).unwrap();
+        let s = match ast {
+            ast::Node::Section(s) => s,
+            _ => panic!(),
+        };
+        let p = match &s.child[0] {
+            ast::Node::Paragraph(p) => p,
+            _ => panic!(),
+        };
+        let f = match &p.child[0] {
+            ast::ParagraphChild::Func(f) => f,
+            _ => panic!(),
+        };
+        use token::Literal;
+        assert_eq!(f.name, "f");
+        assert_eq!(
+            f.arg,
+            vec![
+                Literal::Str("fig.png".to_string()),
+                Literal::Str("a,b)".to_string())
+            ]
+        );
+        assert_eq!(f.get_named_arg("width"), Some(&Literal::Float(0.8)));
+        assert_eq!(
+            f.get_named_arg("alt"),
+            Some(&Literal::Str("a (b), c".to_string()))
+        );
+        assert_eq!(f.block.as_deref(), Some("caption"));
+    }
+
+    #[test]
+    fn comment() {
+        let s = "para # not a comment\n# comment\ncontinued\n\n#[\nblock\n]#\n";
+
+        let ast = parse(s).unwrap();
+        let s1 = match ast {
+            ast::Node::Section(s) => s,
+            _ => panic!(),
+        };
+        assert_eq!(s1.child.len(), 1);
+        match &s1.child[0] {
+            ast::Node::Paragraph(p) => assert!(matches!(
+                p.child.as_slice(),
+                [ast::ParagraphChild::Sentence(s)] if s == "para # not a comment continued"
+            )),
+            _ => panic!(),
+        }
+
+        let opts = parse::Options {
+            keep_comments: true,
+            ..Default::default()
+        };
+        let ast = parse_with_options(s, opts).unwrap();
+        let s2 = match ast {
+            ast::Node::Section(s) => s,
+            _ => panic!(),
+        };
+        assert_eq!(s2.child.len(), 4);
+        match (&s2.child[1], &s2.child[3]) {
+            (ast::Node::Comment(c1), ast::Node::Comment(c2)) => {
+                assert_eq!((c1.body.as_str(), c1.block), ("comment", false));
+                assert_eq!((c2.body.as_str(), c2.block), ("block", true));
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn math() {
+        let s = "Euler: $e^{i\\pi} + 1 = 0$, costs $5 and $10.\n\\$x$\n$$\n\\int_0^1 x\\,dx\n$$\n";
+        let ast = parse(s).unwrap();
+        let p = match ast {
+            ast::Node::Section(mut s) => match s.child.remove(0) {
+                ast::Node::Paragraph(p) => p,
+                _ => panic!(),
+            },
+            _ => panic!(),
+        };
+        match p.child.as_slice() {
+            [ast::ParagraphChild::Sentence(a), ast::ParagraphChild::Math(m1), ast::ParagraphChild::Sentence(b), ast::ParagraphChild::Math(m2)] =>
+            {
+                assert_eq!(a, "Euler: ");
+                assert_eq!((m1.src.as_str(), m1.display), ("e^{i\\pi} + 1 = 0", false));
+                assert_eq!(b, ", costs $5 and $10. $x$ ");
+                assert_eq!((m2.src.as_str(), m2.display), ("\\int_0^1 x\\,dx", true));
+            }
+            c => panic!("{:?}", c),
+        }
+    }
+
+    #[test]
+    fn inline_code() {
+        let s = "run `cargo test` first, a ` stays\n`x` and \\`y`\n";
+        let ast = parse(s).unwrap();
+        let p = match ast {
+            ast::Node::Section(mut s) => match s.child.remove(0) {
+                ast::Node::Paragraph(p) => p,
+                _ => panic!(),
+            },
+            _ => panic!(),
+        };
+        match p.child.as_slice() {
+            [ast::ParagraphChild::Sentence(a), ast::ParagraphChild::Code(c1), ast::ParagraphChild::Sentence(b), ast::ParagraphChild::Code(c2), ast::ParagraphChild::Sentence(d)] =>
+            {
+                assert_eq!(a, "run ");
+                assert_eq!(c1, "cargo test");
+                assert_eq!(b, " first, a ` stays ");
+                assert_eq!(c2, "x");
+                assert_eq!(d, " and `y`");
+            }
+            c => panic!("{:?}", c),
+        }
+    }
+
+    #[test]
+    fn table() {
+        let src =
+            "| `code` | a \\| b |\n| @<jmp>(x) | c |\n\n@<table>(1.10){\n  | @<link>(u){l} |\n}\n";
+        let s = match parse(src).unwrap() {
+            ast::Node::Section(s) => s,
+            _ => panic!(),
+        };
+        let t = match &s.child[0] {
+            ast::Node::Table(t) => t,
+            c => panic!("{:?}", c),
+        };
+        assert!(matches!(
+            t.body[0][0].child.as_slice(),
+            [ast::ParagraphChild::Code(c)] if c == "code"
+        ));
+        assert!(matches!(
+            t.body[0][1].child.as_slice(),
+            [ast::ParagraphChild::Sentence(c)] if c == "a | b"
+        ));
+        match t.body[1][0].child.as_slice() {
+            [ast::ParagraphChild::Jump(j)] => {
+                assert_eq!(&src[j.span.start..j.span.end], "@<jmp>(x)")
+            }
+            c => panic!("{:?}", c),
+        }
+
+        let t = match &s.child[1] {
+            ast::Node::Table(t) => t,
+            c => panic!("{:?}", c),
+        };
+        // as written, not as the number 1.1
+        assert_eq!(t.caption.as_deref(), Some("1.10"));
+        match t.body[0][0].child.as_slice() {
+            [ast::ParagraphChild::Link(l)] => {
+                assert_eq!(&src[l.span.start..l.span.end], "@<link>(u){l}")
+            }
+            c => panic!("{:?}", c),
+        }
+    }
+
+    #[test]
+    fn list() {
+        let s = "- a\n  continued\n- [x] done\n  1. one\n  2. two\n    - deep\n- [ ] todo\n\n3. three\n";
+        let ast = parse(s).unwrap();
+        let mut s = match ast {
+            ast::Node::Section(s) => s,
+            _ => panic!(),
+        };
+        assert_eq!(s.child.len(), 2);
+        let l = match s.child.remove(0) {
+            ast::Node::List(l) => l,
+            _ => panic!(),
+        };
+        assert_eq!(l.start, None);
+        assert_eq!(l.items.len(), 3);
+        assert!(matches!(
+            l.items[0].child.as_slice(),
+            [ast::ParagraphChild::Sentence(s)] if s == "a continued"
+        ));
+        assert_eq!(
+            l.items.iter().map(|i| i.check).collect::<Vec<_>>(),
+            vec![None, Some(true), Some(false)]
+        );
+        let sub = l.items[1].list.as_ref().unwrap();
+        assert_eq!(sub.start, Some(1));
+        assert_eq!(sub.items.len(), 2);
+        assert!(sub.items[1].list.is_some());
+
+        match &s.child[0] {
+            ast::Node::List(l) => assert_eq!(l.start, Some(3)),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn header() {
+        let s = r#"---
+title: writedown
+version: 3
+scale: 0.8
+draft: false
+date: 2021-04-01
+tags: [rust, "a, b", 1]
+---
+body
+"#;
+        let ast = parse(s).unwrap();
+        let s = match ast {
+            ast::Node::Section(s) => s,
+            _ => panic!(),
+        };
+        let h = match &s.child[0] {
+            ast::Node::Header(h) => h,
+            _ => panic!(),
+        };
+        assert_eq!(h.title.as_deref(), Some("writedown"));
+        assert_eq!(h.get("version"), Some(&token::Literal::Int(3)));
+        assert_eq!(h.get("scale"), Some(&token::Literal::Float(0.8)));
+        assert_eq!(h.get("draft"), Some(&token::Literal::Bool(false)));
+        assert_eq!(
+            h.get("date"),
+            Some(&token::Literal::Date(token::Date {
+                year: 2021,
+                month: 4,
+                day: 1
+            }))
+        );
+        assert_eq!(
+            h.get("tags"),
+            Some(&token::Literal::List(vec![
+                token::Literal::Str("rust".to_string()),
+                token::Literal::Str("a, b".to_string()),
+                token::Literal::Int(1),
+            ]))
+        );
+        assert!(matches!(&s.child[1], ast::Node::Paragraph(_)));
+    }
 }
@@ -0,0 +1,302 @@
+use crate::ast;
+use crate::func::Expansion;
+use crate::render::{percent, Context};
+use crate::token;
+
+pub fn render(node: &ast::Node, ctx: &mut Context) -> String {
+    ctx.prepare(node);
+    let mut s = render_node(node, ctx);
+    s += &render_footnotes(ctx);
+    s
+}
+
+fn render_node(node: &ast::Node, ctx: &mut Context) -> String {
+    match node {
+        ast::Node::Header(_) => String::new(),
+        ast::Node::Section(s) => render_section(s, ctx),
+        ast::Node::Paragraph(p) => render_paragraph(p, ctx),
+        ast::Node::Figure(f) => render_figure(f, ctx),
+        ast::Node::Table(t) => render_table(t, ctx),
+        ast::Node::List(l) => render_list(l, ctx),
+        ast::Node::Comment(c) if ctx.draft => {
+            format!("<aside class=\"comment\">{}</aside>\n", escape(&c.body))
+        }
+        ast::Node::Block(ast::Block::Code(c)) => {
+            format!("<pre{}><code>{}</code></pre>\n", id(&c.tag), escape(&c.src))
+        }
+        _ => String::new(),
+    }
+}
+
+fn render_section(s: &ast::Section, ctx: &mut Context) -> String {
+    let mut cs = String::new();
+    for c in &s.child {
+        cs += &render_node(c, ctx);
+    }
+
+    // the root section has no heading
+    if s.level == 0 {
+        return cs;
+    }
+    let l = std::cmp::min(s.level + 1, 6);
+    format!(
+        "<h{}{}>{}</h{}>\n{}",
+        l,
+        id(&s.tag),
+        escape(&s.title),
+        l,
+        cs
+    )
+}
+
+fn render_paragraph(p: &ast::Paragraph, ctx: &mut Context) -> String {
+    let cs = render_children(&p.child, ctx);
+    // e.g. only `@<ftref>` definitions
+    if cs.is_empty() && p.tag.is_none() {
+        return String::new();
+    }
+    format!("<p{}>{}</p>\n", id(&p.tag), cs)
+}
+
+fn render_figure(f: &ast::Figure, ctx: &mut Context) -> String {
+    ctx.figures += 1;
+
+    let width = match &f.width {
+        Some(token::Literal::Float(w)) => format!(" style=\"width: {}\"", percent(*w)),
+        Some(token::Literal::Int(w)) => format!(" width=\"{}\"", w),
+        Some(w) => format!(" style=\"width: {}\"", escape(&w.to_string())),
+        None => String::new(),
+    };
+    let alt = f.alt.as_ref().or(f.caption.as_ref());
+    let mut s = format!(
+        "<figure{}>\n<img src=\"{}\" alt=\"{}\"{}>\n",
+        id(&f.tag),
+        escape(&f.path),
+        escape(alt.map(|a| a.as_str()).unwrap_or_default()),
+        width
+    );
+    if let Some(c) = &f.caption {
+        s += &format!(
+            "<figcaption>Figure {}: {}</figcaption>\n",
+            ctx.figures,
+            escape(c)
+        );
+    }
+    s += "</figure>\n";
+    s
+}
+
+fn render_table(t: &ast::Table, ctx: &mut Context) -> String {
+    ctx.tables += 1;
+
+    let mut s = format!("<table{}>\n", id(&t.tag));
+    if let Some(c) = &t.caption {
+        s += &format!("<caption>Table {}: {}</caption>\n", ctx.tables, escape(c));
+    }
+    let rows = |rows: &[Vec<ast::Cell>], cell: &str, ctx: &mut Context| {
+        let mut s = String::new();
+        for r in rows {
+            s += "<tr>";
+            for (i, c) in r.iter().enumerate() {
+                let align = match t.align(i) {
+                    ast::Align::Default => "",
+                    ast::Align::Left => " style=\"text-align: left\"",
+                    ast::Align::Center => " style=\"text-align: center\"",
+                    ast::Align::Right => " style=\"text-align: right\"",
+                };
+                s += &format!(
+                    "<{}{}>{}</{}>",
+                    cell,
+                    align,
+                    render_children(&c.child, ctx),
+                    cell
+                );
+            }
+            s += "</tr>\n";
+        }
+        s
+    };
+    if !t.header.is_empty() {
+        s += &format!("<thead>\n{}</thead>\n", rows(&t.header, "th", ctx));
+    }
+    s += &format!("<tbody>\n{}</tbody>\n", rows(&t.body, "td", ctx));
+    s += "</table>\n";
+    s
+}
+
+fn render_list(l: &ast::List, ctx: &mut Context) -> String {
+    let (tag, start) = match l.start {
+        None => ("ul", String::new()),
+        Some(1) => ("ol", String::new()),
+        Some(n) => ("ol", format!(" start=\"{}\"", n)),
+    };
+
+    let mut s = format!("<{}{}>\n", tag, start);
+    for i in &l.items {
+        s += "<li>";
+        match i.check {
+            Some(true) => s += "<input type=\"checkbox\" disabled checked> ",
+            Some(false) => s += "<input type=\"checkbox\" disabled> ",
+            None => {}
+        }
+        s += &render_children(&i.child, ctx);
+        if let Some(l) = &i.list {
+            s += "\n";
+            s += &render_list(l, ctx);
+        }
+        s += "</li>\n";
+    }
+    s += &format!("</{}>\n", tag);
+    s
+}
+
+fn id(tag: &Option<String>) -> String {
+    match tag {
+        Some(t) => format!(" id=\"{}\"", escape(t)),
+        None => String::new(),
+    }
+}
+
+fn render_children(child: &[ast::ParagraphChild], ctx: &mut Context) -> String {
+    let mut cs = String::new();
+    for c in child {
+        cs += &render_child(c, ctx);
+    }
+    cs
+}
+
+fn render_child(c: &ast::ParagraphChild, ctx: &mut Context) -> String {
+    match c {
+        ast::ParagraphChild::Sentence(s) => escape(s),
+        ast::ParagraphChild::Func(f) => match ctx.expand(f) {
+            Some(Expansion::Nodes(n)) => render_children(&n, ctx),
+            Some(Expansion::Raw(s)) => s,
+            None => f
+                .block
+                .as_deref()
+                .map(|b| escape(&token::unescape_text(b)))
+                .unwrap_or_default(),
+        },
+        ast::ParagraphChild::Tag(t) => match ctx.footnote_for_tag(t) {
+            Some(body) => {
+                let body = body.to_string();
+                footnote_ref(ctx.add_footnote(&body))
+            }
+            None => format!("<a id=\"{}\"></a>", escape(t)),
+        },
+        ast::ParagraphChild::Jump(j) => match ctx.jump_label(j) {
+            Some(l) => format!("<a href=\"#{}\">{}</a>", escape(&j.tag), escape(&l)),
+            None => escape(&j.tag),
+        },
+        ast::ParagraphChild::Mention(m) => match ctx.mention.resolve(m) {
+            Some(url) => format!(
+                "<a class=\"mention\" href=\"{}\">{}</a>",
+                escape(&url),
+                escape(&m.to_string())
+            ),
+            None => format!("<span class=\"mention\">{}</span>", escape(&m.to_string())),
+        },
+        ast::ParagraphChild::Link(l) => {
+            let title = match &l.title {
+                Some(t) => format!(" title=\"{}\"", escape(t)),
+                None => String::new(),
+            };
+            format!(
+                "<a href=\"{}\"{}>{}</a>",
+                escape(&l.href()),
+                title,
+                escape(l.text())
+            )
+        }
+        ast::ParagraphChild::SoftBreak => "\n".to_string(),
+        ast::ParagraphChild::LinkDef(_) => String::new(),
+        ast::ParagraphChild::Ruby(r) => ruby(r),
+        // for MathJax or KaTeX, as Pandoc writes it
+        ast::ParagraphChild::Code(c) => format!("<code>{}</code>", escape(c)),
+        ast::ParagraphChild::Math(m) if m.display => format!(
+            "<span class=\"math display\">\\[{}\\]</span>",
+            escape(&m.src)
+        ),
+        ast::ParagraphChild::Math(m) => format!(
+            "<span class=\"math inline\">\\({}\\)</span>",
+            escape(&m.src)
+        ),
+        ast::ParagraphChild::LinkRef(r) => match ctx.link_for_ref(r) {
+            Some(l) => render_child(&ast::ParagraphChild::Link(l), ctx),
+            None => escape(r.label.as_ref().unwrap_or(&r.name)),
+        },
+        ast::ParagraphChild::Footnote(ft) => match &ft.tag {
+            // defined here, rendered where the tag is
+            Some(_) => String::new(),
+            None => footnote_ref(ctx.add_footnote(&ft.body)),
+        },
+    }
+}
+
+/// `<ruby>漢<rt>かん</rt>字<rt>じ</rt></ruby>`; also used by the Markdown
+/// backend, which has no syntax of its own for ruby.
+pub fn ruby(r: &ast::Ruby) -> String {
+    let mut s = String::from("<ruby>");
+    for (b, t) in r.base.iter().zip(&r.text) {
+        s += &format!("{}<rt>{}</rt>", escape(b), escape(t));
+    }
+    s += "</ruby>";
+    s
+}
+
+fn footnote_ref(n: usize) -> String {
+    format!(
+        "<sup id=\"fnref-{}\"><a href=\"#fn-{}\">{}</a></sup>",
+        n, n, n
+    )
+}
+
+fn render_footnotes(ctx: &Context) -> String {
+    if ctx.footnotes.is_empty() {
+        return String::new();
+    }
+
+    let mut s = String::from("<section class=\"footnotes\">\n<ol>\n");
+    for (i, body) in ctx.footnotes.iter().enumerate() {
+        let n = i + 1;
+        s += &format!(
+            "<li id=\"fn-{}\">{} <a href=\"#fnref-{}\">&#8617;</a></li>\n",
+            n,
+            escape(body),
+            n
+        );
+    }
+    s += "</ol>\n</section>\n";
+    s
+}
+
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::{html, Backend, Context};
+    use crate::*;
+
+    #[test]
+    fn list() {
+        let s = "- [x] write\n  1. draft\n  2. review\n- [ ] publish\n";
+        let ast = parse(s).unwrap();
+        let mut ctx = Context::new(Backend::Html);
+        let h = html::render(&ast, &mut ctx);
+        assert!(h.contains("<ul>\n<li><input type=\"checkbox\" disabled checked> write"));
+        assert!(h.contains("<ol>\n<li>draft</li>\n<li>review</li>\n</ol>\n</li>"));
+    }
+}
@@ -0,0 +1,464 @@
+use crate::ast;
+use crate::func::Expansion;
+use crate::link;
+use crate::render::Context;
+use crate::token;
+
+/// Options of the LaTeX backend.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub code: CodeStyle,
+    /// Write a complete document whose preamble is built from the header,
+    /// instead of a fragment to `\input`.
+    pub standalone: bool,
+    /// Use `jlreq` with LuaTeX-ja; a header with `lang: ja` selects it as
+    /// well. Compile with LuaLaTeX. `pxrubrica` is loaded for ruby either
+    /// way.
+    pub japanese: bool,
+}
+
+/// Environment for code blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CodeStyle {
+    /// `lstlisting` from `listings`, which needs no external tools.
+    #[default]
+    Listings,
+    /// `minted`, highlighted by Pygments; needs `-shell-escape`.
+    Minted,
+    Verbatim,
+}
+
+pub fn render(node: &ast::Node, ctx: &mut Context) -> String {
+    ctx.prepare(node);
+    let body = render_node(node, ctx);
+    if !ctx.latex.standalone {
+        return body;
+    }
+
+    let mut header = None;
+    let mut ruby = false;
+    node.walk(&mut |n| {
+        if let ast::Node::Header(h) = n {
+            header.get_or_insert(h);
+        }
+        ruby |= n
+            .inline()
+            .into_iter()
+            .flatten()
+            .any(|c| matches!(c, ast::ParagraphChild::Ruby(_)));
+    });
+    document(header, &body, ruby, &ctx.latex)
+}
+
+// `ruby`: whether `\ruby` is used, which needs pxrubrica
+fn document(header: Option<&ast::Header>, body: &str, ruby: bool, opts: &Options) -> String {
+    let get = |k: &str| header.and_then(|h| h.get(k));
+    let japanese = opts.japanese
+        || matches!(get("lang").and_then(|l| l.as_str()), Some(l) if l.starts_with("ja"));
+
+    // under LuaLaTeX, jlreq loads LuaTeX-ja by itself
+    let mut s = String::from(if japanese {
+        "\\documentclass{jlreq}\n"
+    } else {
+        "\\documentclass{article}\n"
+    });
+    s += "\\usepackage{amsmath}\n\\usepackage{graphicx}\n";
+    s += match opts.code {
+        CodeStyle::Listings => {
+            "\\usepackage{listings}\n\\lstset{basicstyle=\\ttfamily\\small, breaklines=true, columns=fullflexible}\n"
+        }
+        CodeStyle::Minted => "\\usepackage{minted}\n",
+        CodeStyle::Verbatim => "",
+    };
+    if japanese || ruby {
+        s += "\\usepackage{pxrubrica}\n";
+    }
+    s += "\\usepackage{hyperref}\n\n";
+
+    let title = header.and_then(|h| h.title.as_ref());
+    if let Some(t) = title {
+        s += &format!("\\title{{{}}}\n", escape(t));
+        if let Some(a) = header.and_then(|h| h.author.as_ref()) {
+            s += &format!("\\author{{{}}}\n", escape(a));
+        }
+        let date = get("date").map(|d| escape(&d.to_string()));
+        s += &format!("\\date{{{}}}\n", date.unwrap_or_default());
+    }
+    s += "\n\\begin{document}\n";
+    if title.is_some() {
+        s += "\\maketitle\n";
+    }
+    if let Some(d) = header.and_then(|h| h.description.as_ref()) {
+        s += &format!("\\begin{{abstract}}\n{}\n\\end{{abstract}}\n", escape(d));
+    }
+    s += "\n";
+    s += body;
+    s += "\\end{document}\n";
+    s
+}
+
+fn render_node(node: &ast::Node, ctx: &mut Context) -> String {
+    match node {
+        ast::Node::Header(_) => String::new(),
+        ast::Node::Section(s) => render_section(s, ctx),
+        ast::Node::Paragraph(p) => {
+            let cs = render_children(&p.child, ctx);
+            if cs.is_empty() && p.tag.is_none() {
+                return String::new();
+            }
+            format!("{}{}\n\n", label(&p.tag), cs)
+        }
+        ast::Node::Figure(f) => render_figure(f, ctx),
+        ast::Node::Table(t) => render_table(t, ctx),
+        ast::Node::List(l) => render_list(l, 0, ctx) + "\n",
+        ast::Node::Comment(c) if ctx.draft => {
+            format!("\\marginpar{{\\footnotesize {}}}\n\n", escape(&c.body))
+        }
+        ast::Node::Block(ast::Block::Code(c)) => render_code(c, ctx),
+        _ => String::new(),
+    }
+}
+
+fn render_section(s: &ast::Section, ctx: &mut Context) -> String {
+    let mut cs = String::new();
+    for c in &s.child {
+        cs += &render_node(c, ctx);
+    }
+
+    let cmd = match s.level {
+        0 => return cs,
+        1 => "section",
+        2 => "subsection",
+        3 => "subsubsection",
+        4 => "paragraph",
+        _ => "subparagraph",
+    };
+    format!(
+        "\\{}{{{}}}{}\n\n{}",
+        cmd,
+        escape(&s.title),
+        label(&s.tag),
+        cs
+    )
+}
+
+fn render_figure(f: &ast::Figure, ctx: &mut Context) -> String {
+    ctx.figures += 1;
+
+    let width = match &f.width {
+        Some(token::Literal::Float(w)) => format!("[width={}\\linewidth]", w),
+        Some(token::Literal::Int(w)) => format!("[width={}px]", w),
+        Some(w) => format!("[width={}]", w),
+        None => String::new(),
+    };
+    let mut s = format!(
+        "\\begin{{figure}}[htbp]\n\\centering\n\\includegraphics{}{{{}}}\n",
+        width, f.path
+    );
+    if let Some(c) = &f.caption {
+        s += &format!("\\caption{{{}}}\n", escape(c));
+    }
+    s += &label(&f.tag);
+    if f.tag.is_some() {
+        s += "\n";
+    }
+    s += "\\end{figure}\n\n";
+    s
+}
+
+fn render_table(t: &ast::Table, ctx: &mut Context) -> String {
+    ctx.tables += 1;
+
+    let spec: String = (0..t.columns())
+        .map(|i| match t.align(i) {
+            ast::Align::Center => 'c',
+            ast::Align::Right => 'r',
+            _ => 'l',
+        })
+        .collect();
+
+    let mut s = String::from("\\begin{table}[htbp]\n\\centering\n");
+    if let Some(c) = &t.caption {
+        s += &format!("\\caption{{{}}}\n", escape(c));
+    }
+    if t.tag.is_some() {
+        s += &format!("{}\n", label(&t.tag));
+    }
+    s += &format!("\\begin{{tabular}}{{{}}}\n\\hline\n", spec);
+    for rows in [&t.header, &t.body] {
+        if rows.is_empty() {
+            continue;
+        }
+        for r in rows {
+            let cells: Vec<String> = r.iter().map(|c| render_children(&c.child, ctx)).collect();
+            s += &format!("{} \\\\\n", cells.join(" & "));
+        }
+        s += "\\hline\n";
+    }
+    s += "\\end{tabular}\n\\end{table}\n\n";
+    s
+}
+
+fn render_code(c: &ast::Code, ctx: &Context) -> String {
+    match ctx.latex.code {
+        CodeStyle::Listings => {
+            let mut opts = Vec::new();
+            if let Some(l) = c.lang.as_deref().and_then(lst_language) {
+                opts.push(format!("language={}", l));
+            }
+            // numbers the listing for `\ref` without a caption
+            if let Some(t) = &c.tag {
+                opts.push(format!("label={{{}}}", t));
+            }
+            let opts = if opts.is_empty() {
+                String::new()
+            } else {
+                format!("[{}]", opts.join(", "))
+            };
+            format!(
+                "\\begin{{lstlisting}}{}\n{}\\end{{lstlisting}}\n\n",
+                opts, c.src
+            )
+        }
+        CodeStyle::Minted => {
+            // minted's `listing` counter, so that `\ref` gives a number
+            let label = match &c.tag {
+                Some(t) => format!("\\refstepcounter{{listing}}\\label{{{}}}\n", t),
+                None => String::new(),
+            };
+            format!(
+                "{}\\begin{{minted}}{{{}}}\n{}\\end{{minted}}\n\n",
+                label,
+                c.lang.as_deref().unwrap_or("text"),
+                c.src
+            )
+        }
+        CodeStyle::Verbatim => format!(
+            "{}\\begin{{verbatim}}\n{}\\end{{verbatim}}\n\n",
+            label(&c.tag),
+            c.src
+        ),
+    }
+}
+
+// `listings` stops with an error on a language it does not know
+fn lst_language(lang: &str) -> Option<&'static str> {
+    Some(match lang.to_ascii_lowercase().as_str() {
+        "c" => "C",
+        "cpp" | "c++" => "C++",
+        "java" => "Java",
+        "python" | "py" => "Python",
+        "ruby" | "rb" => "Ruby",
+        "perl" => "Perl",
+        "haskell" | "hs" => "Haskell",
+        "sh" | "bash" | "shell" => "bash",
+        "make" | "makefile" => "make",
+        "sql" => "SQL",
+        "html" => "HTML",
+        "xml" => "XML",
+        "tex" | "latex" => "TeX",
+        _ => return None,
+    })
+}
+
+fn label(tag: &Option<String>) -> String {
+    match tag {
+        Some(t) => format!("\\label{{{}}}", t),
+        None => String::new(),
+    }
+}
+
+// `depth` is the number of enclosing enumerate environments
+fn render_list(l: &ast::List, depth: usize, ctx: &mut Context) -> String {
+    let env = if l.start.is_some() {
+        "enumerate"
+    } else {
+        "itemize"
+    };
+    let mut s = format!("\\begin{{{}}}\n", env);
+    let depth = match l.start {
+        Some(n) => {
+            if n != 1 && depth < 4 {
+                let counter = ["enumi", "enumii", "enumiii", "enumiv"][depth];
+                s += &format!("\\setcounter{{{}}}{{{}}}\n", counter, n - 1);
+            }
+            depth + 1
+        }
+        None => depth,
+    };
+
+    for i in &l.items {
+        s += match i.check {
+            Some(true) => "\\item[{[x]}] ",
+            Some(false) => "\\item[{[ ]}] ",
+            None => "\\item ",
+        };
+        s += &render_children(&i.child, ctx);
+        s += "\n";
+        if let Some(l) = &i.list {
+            s += &render_list(l, depth, ctx);
+        }
+    }
+    s += &format!("\\end{{{}}}\n", env);
+    s
+}
+
+fn render_children(child: &[ast::ParagraphChild], ctx: &mut Context) -> String {
+    let mut cs = String::new();
+    for c in child {
+        cs += &render_child(c, ctx);
+    }
+    cs
+}
+
+fn render_child(c: &ast::ParagraphChild, ctx: &mut Context) -> String {
+    match c {
+        ast::ParagraphChild::Sentence(s) => escape(s),
+        ast::ParagraphChild::Func(f) => match ctx.expand(f) {
+            Some(Expansion::Nodes(n)) => render_children(&n, ctx),
+            Some(Expansion::Raw(s)) => s,
+            None => f
+                .block
+                .as_deref()
+                .map(|b| escape(&token::unescape_text(b)))
+                .unwrap_or_default(),
+        },
+        ast::ParagraphChild::Tag(t) => match ctx.footnote_for_tag(t) {
+            Some(body) => {
+                let body = body.to_string();
+                ctx.add_footnote(&body);
+                format!("\\footnote{{{}}}", escape(&body))
+            }
+            None => format!("\\label{{{}}}", t),
+        },
+        ast::ParagraphChild::Jump(j) => {
+            let t = &j.tag;
+            let numbered = ctx.tags.get(t).map(|t| t.number.is_some());
+            match (ctx.jump_label(j), numbered) {
+                (Some(_), Some(true)) => format!("\\ref{{{}}}", t),
+                (Some(l), _) => format!("\\hyperref[{}]{{{}}}", t, escape(&l)),
+                (None, _) => escape(t),
+            }
+        }
+        ast::ParagraphChild::Mention(m) => match ctx.mention.resolve(m) {
+            Some(url) => format!("\\href{{{}}}{{{}}}", url, escape(&m.to_string())),
+            None => escape(&m.to_string()),
+        },
+        ast::ParagraphChild::Link(l) => match (l.kind, &l.label) {
+            (link::Link::URL, None) => format!("\\url{{{}}}", escape_url(&l.url)),
+            _ => format!(
+                "\\href{{{}}}{{{}}}",
+                escape_url(&l.href()),
+                escape(l.text())
+            ),
+        },
+        ast::ParagraphChild::Math(m) if m.display => format!("\\[{}\\]", m.src),
+        ast::ParagraphChild::Math(m) => format!("\\({}\\)", m.src),
+        ast::ParagraphChild::Code(c) => format!("\\texttt{{{}}}", escape(c)),
+        ast::ParagraphChild::SoftBreak => "\n".to_string(),
+        ast::ParagraphChild::LinkDef(_) => String::new(),
+        // pxrubrica: `\ruby{漢|字}{かん|じ}`
+        ast::ParagraphChild::Ruby(r) => {
+            let base: Vec<String> = r.base.iter().map(|b| escape(b)).collect();
+            let text: Vec<String> = r.text.iter().map(|t| escape(t)).collect();
+            format!("\\ruby{{{}}}{{{}}}", base.join("|"), text.join("|"))
+        }
+        ast::ParagraphChild::LinkRef(r) => match ctx.link_for_ref(r) {
+            Some(l) => render_child(&ast::ParagraphChild::Link(l), ctx),
+            None => escape(r.label.as_ref().unwrap_or(&r.name)),
+        },
+        ast::ParagraphChild::Footnote(ft) => match &ft.tag {
+            Some(_) => String::new(),
+            None => {
+                ctx.add_footnote(&ft.body);
+                format!("\\footnote{{{}}}", escape(&ft.body))
+            }
+        },
+    }
+}
+
+fn escape_url(s: &str) -> String {
+    s.replace('%', "\\%").replace('#', "\\#")
+}
+
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\textbackslash{}"),
+            '~' => out.push_str("\\textasciitilde{}"),
+            '^' => out.push_str("\\textasciicircum{}"),
+            // not in the OT1 encoding
+            '<' => out.push_str("\\textless{}"),
+            '>' => out.push_str("\\textgreater{}"),
+            '|' => out.push_str("\\textbar{}"),
+            '&' | '%' | '$' | '#' | '_' | '{' | '}' => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::{latex, Backend, Context};
+    use crate::*;
+
+    #[test]
+    fn latex() {
+        let s = r#"---
+title: 仕様書
+author: sksat
+date: 2021-04-01
+lang: ja
+---
+
+= 概要 @[intro]
+
+50% & $x_1^2$ see @<jmp>(code) @<ft>{注}
+
+@[code]
+```:rust
+fn main() {}
+```
+
+$$
+E = mc^2
+$$
+"#;
+        let ast = parse(s).unwrap();
+
+        let mut ctx = Context::new(Backend::Latex);
+        let l = latex::render(&ast, &mut ctx);
+        assert!(l.starts_with("\\section{概要}\\label{intro}\n\n"), "{}", l);
+        assert!(
+            l.contains("50\\% \\& \\(x_1^2\\) see \\ref{code} \\footnote{注}"),
+            "{}",
+            l
+        );
+        assert!(l.contains("\\begin{lstlisting}[label={code}]\nfn main() {}\n\\end{lstlisting}"));
+        assert!(l.contains("\\[E = mc^2\\]"));
+
+        let mut ctx = Context::new(Backend::Latex);
+        ctx.latex.code = latex::CodeStyle::Minted;
+        ctx.latex.standalone = true;
+        let l = latex::render(&ast, &mut ctx);
+        assert!(l.starts_with("\\documentclass{jlreq}\n"));
+        assert!(l.contains("\\usepackage{minted}\n\\usepackage{pxrubrica}\n"));
+        assert!(l.contains("\\title{仕様書}\n\\author{sksat}\n\\date{2021-04-01}\n"));
+        assert!(l.contains("\\refstepcounter{listing}\\label{code}\n\\begin{minted}{rust}\n"));
+        assert!(l.ends_with("\\end{document}\n"));
+
+        let ast = parse("@<ruby>(Tokyo, toukyou)\n").unwrap();
+        let mut ctx = Context::new(Backend::Latex);
+        ctx.latex.standalone = true;
+        let l = latex::render(&ast, &mut ctx);
+        assert!(l.starts_with("\\documentclass{article}\n"));
+        assert!(l.contains("\\usepackage{pxrubrica}\n"));
+
+        assert_eq!(latex::escape("a_b {c} ~^\\ <|>"), "a\\_b \\{c\\} \\textasciitilde{}\\textasciicircum{}\\textbackslash{} \\textless{}\\textbar{}\\textgreater{}");
+    }
+}
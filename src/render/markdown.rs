@@ -0,0 +1,332 @@
+//! CommonMark with GitHub extensions.
+//!
+//! - Sections become `#` headings, one `#` per `Section::level` (at most
+//!   six); the header becomes YAML front matter.
+//! - Code blocks are fenced with their language, lists use `-`, `1.` and
+//!   `[ ]`/`[x]`, and tables are GFM tables whose first header row is the
+//!   only one Markdown can show; further header rows become body rows.
+//! - Footnotes use `[^n]` with the definitions at the end.
+//!
+//! Writedown-only constructs fall back as follows:
+//!
+//! - Tags become HTML anchors (`<a id="tag"></a>`) and `@<jmp>` a link to
+//!   them with the same label as in the other backends.
+//! - Figure and table captions become an italic paragraph, numbered like
+//!   `Figure 1: ...`.
+//! - Ruby is written as HTML (`<ruby>`), which GitHub renders.
+//! - Mentions become links when `Context::mention` resolves them.
+//! - Custom functions use the `FunctionRegistry`; without a handler their
+//!   block is written as text and a warning is reported, except for
+//!   `@<b>`, `@<i>` and `@<code>` (see `Markup`), which become `**`, `*`
+//!   and code spans.
+//! - Comments are dropped, or shown as a `> **Comment:**` quote in draft
+//!   mode.
+
+use crate::ast;
+use crate::func::Expansion;
+use crate::link;
+use crate::render::html;
+use crate::render::Context;
+use crate::token;
+
+/// Render as CommonMark with GitHub extensions (tables, footnotes).
+pub fn render(node: &ast::Node, ctx: &mut Context) -> String {
+    ctx.prepare(node);
+    let mut s = render_node(node, ctx);
+    s += &render_footnotes(ctx);
+    s
+}
+
+fn render_node(node: &ast::Node, ctx: &mut Context) -> String {
+    match node {
+        ast::Node::Header(h) => {
+            let mut s = String::from("---\n");
+            for (k, v) in &h.fields {
+                s += &format!("{}: {}\n", k, yaml(v));
+            }
+            s + "---\n\n"
+        }
+        ast::Node::Section(s) => render_section(s, ctx),
+        ast::Node::Paragraph(p) => {
+            let cs = render_children(&p.child, ctx);
+            if cs.is_empty() && p.tag.is_none() {
+                return String::new();
+            }
+            format!("{}{}\n\n", anchor(&p.tag), cs)
+        }
+        ast::Node::Figure(f) => render_figure(f, ctx),
+        ast::Node::Table(t) => render_table(t, ctx),
+        ast::Node::List(l) => render_list(l, "", ctx) + "\n",
+        ast::Node::Comment(c) if ctx.draft => {
+            let body: Vec<String> = c.body.lines().map(escape).collect();
+            format!("> **Comment:** {}\n\n", body.join("\n> "))
+        }
+        ast::Node::Block(ast::Block::Code(c)) => format!(
+            "{}```{}\n{}```\n\n",
+            anchor(&c.tag),
+            c.lang.as_deref().unwrap_or_default(),
+            c.src
+        ),
+        _ => String::new(),
+    }
+}
+
+fn render_section(s: &ast::Section, ctx: &mut Context) -> String {
+    let mut cs = String::new();
+    for c in &s.child {
+        cs += &render_node(c, ctx);
+    }
+
+    if s.level == 0 {
+        return cs;
+    }
+    let l = std::cmp::min(s.level, 6);
+    format!(
+        "{} {}{}\n\n{}",
+        "#".repeat(l),
+        escape(&s.title),
+        anchor(&s.tag).trim_end(),
+        cs
+    )
+}
+
+fn render_figure(f: &ast::Figure, ctx: &mut Context) -> String {
+    ctx.figures += 1;
+
+    let alt = f.alt.as_ref().or(f.caption.as_ref());
+    let mut s = format!(
+        "{}![{}]({})\n",
+        anchor(&f.tag),
+        escape(alt.map(|a| a.as_str()).unwrap_or_default()),
+        f.path
+    );
+    if let Some(c) = &f.caption {
+        s += &format!("\n*Figure {}: {}*\n", ctx.figures, escape(c));
+    }
+    s += "\n";
+    s
+}
+
+fn render_table(t: &ast::Table, ctx: &mut Context) -> String {
+    ctx.tables += 1;
+
+    let mut s = anchor(&t.tag);
+    if let Some(c) = &t.caption {
+        s += &format!("*Table {}: {}*\n\n", ctx.tables, escape(c));
+    }
+
+    let n = t.columns();
+    let row = |r: &[ast::Cell], ctx: &mut Context| {
+        let mut s = String::from("|");
+        for i in 0..n {
+            let c = match r.get(i) {
+                Some(c) => render_children(&c.child, ctx),
+                None => String::new(),
+            };
+            s += &format!(" {} |", c);
+        }
+        s + "\n"
+    };
+
+    // GFM tables need exactly one header row
+    let empty = Vec::new();
+    let (header, rest) = t.header.split_first().unwrap_or((&empty, &[]));
+    s += &row(header, ctx);
+    s += "|";
+    for i in 0..n {
+        s += match t.align(i) {
+            ast::Align::Default => " --- |",
+            ast::Align::Left => " :-- |",
+            ast::Align::Center => " :-: |",
+            ast::Align::Right => " --: |",
+        };
+    }
+    s += "\n";
+    for r in rest.iter().chain(t.body.iter()) {
+        s += &row(r, ctx);
+    }
+    s += "\n";
+    s
+}
+
+fn render_list(l: &ast::List, indent: &str, ctx: &mut Context) -> String {
+    let mut s = String::new();
+    for (n, i) in l.items.iter().enumerate() {
+        let marker = match l.start {
+            Some(start) => format!("{}. ", start + n),
+            None => "- ".to_string(),
+        };
+        let check = match i.check {
+            Some(true) => "[x] ",
+            Some(false) => "[ ] ",
+            None => "",
+        };
+        // continuation lines and nested lists line up with the text
+        let inner = format!("{}{}", indent, " ".repeat(marker.len()));
+        let text = render_children(&i.child, ctx).replace('\n', &format!("\n{}", inner));
+        s += &format!("{}{}{}{}\n", indent, marker, check, text);
+        if let Some(l) = &i.list {
+            s += &render_list(l, &inner, ctx);
+        }
+    }
+    s
+}
+
+fn yaml(v: &token::Literal) -> String {
+    match v {
+        token::Literal::Str(s) => format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"")),
+        token::Literal::List(l) => {
+            let l: Vec<String> = l.iter().map(yaml).collect();
+            format!("[{}]", l.join(", "))
+        }
+        _ => v.to_string(),
+    }
+}
+
+fn anchor(tag: &Option<String>) -> String {
+    match tag {
+        Some(t) => format!("<a id=\"{}\"></a>\n", t),
+        None => String::new(),
+    }
+}
+
+fn render_children(child: &[ast::ParagraphChild], ctx: &mut Context) -> String {
+    let mut cs = String::new();
+    for c in child {
+        cs += &render_child(c, ctx);
+    }
+    cs
+}
+
+fn render_child(c: &ast::ParagraphChild, ctx: &mut Context) -> String {
+    match c {
+        ast::ParagraphChild::Sentence(s) => escape(s),
+        ast::ParagraphChild::Func(f) => match ctx.expand(f) {
+            Some(Expansion::Nodes(n)) => render_children(&n, ctx),
+            Some(Expansion::Raw(s)) => s,
+            None => f
+                .block
+                .as_deref()
+                .map(|b| escape(&token::unescape_text(b)))
+                .unwrap_or_default(),
+        },
+        ast::ParagraphChild::Tag(t) => match ctx.footnote_for_tag(t) {
+            Some(body) => {
+                let body = body.to_string();
+                format!("[^{}]", ctx.add_footnote(&body))
+            }
+            None => format!("<a id=\"{}\"></a>", t),
+        },
+        ast::ParagraphChild::Jump(j) => match ctx.jump_label(j) {
+            Some(l) => format!("[{}](#{})", escape(&l), j.tag),
+            None => escape(&j.tag),
+        },
+        ast::ParagraphChild::Mention(m) => match ctx.mention.resolve(m) {
+            Some(url) => format!("[{}]({})", escape(&m.to_string()), url),
+            None => escape(&m.to_string()),
+        },
+        ast::ParagraphChild::Link(l) => match (&l.label, &l.title, l.kind) {
+            (None, None, link::Link::Email) => format!("<{}>", l.url),
+            (None, None, _) if link::has_scheme(&l.url) => format!("<{}>", l.url),
+            (_, Some(t), _) => format!(
+                "[{}]({} \"{}\")",
+                escape(l.text()),
+                l.href(),
+                t.replace('"', "\\\"")
+            ),
+            _ => format!("[{}]({})", escape(l.text()), l.href()),
+        },
+        ast::ParagraphChild::SoftBreak => "\n".to_string(),
+        ast::ParagraphChild::LinkDef(_) => String::new(),
+        ast::ParagraphChild::Ruby(r) => html::ruby(r),
+        // GitHub's `$...$` and `$$...$$`
+        ast::ParagraphChild::Math(m) => m.to_string(),
+        // a code span holding a backquote needs a longer fence
+        ast::ParagraphChild::Code(c) if c.contains('`') => format!("`` {} ``", c),
+        ast::ParagraphChild::Code(c) => format!("`{}`", c),
+        ast::ParagraphChild::LinkRef(r) => match ctx.link_for_ref(r) {
+            Some(l) => render_child(&ast::ParagraphChild::Link(l), ctx),
+            None => escape(r.label.as_ref().unwrap_or(&r.name)),
+        },
+        ast::ParagraphChild::Footnote(ft) => match &ft.tag {
+            Some(_) => String::new(),
+            None => format!("[^{}]", ctx.add_footnote(&ft.body)),
+        },
+    }
+}
+
+fn render_footnotes(ctx: &Context) -> String {
+    let mut s = String::new();
+    for (i, body) in ctx.footnotes.iter().enumerate() {
+        s += &format!("[^{}]: {}\n", i + 1, escape(body));
+    }
+    s
+}
+
+/// Backslash-escape characters that would start Markdown syntax.
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(
+            c,
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|'
+        ) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::{markdown, Backend, Context};
+    use crate::*;
+
+    #[test]
+    fn markdown() {
+        let s = r#"---
+title: Notes
+tags: [a, b]
+---
+
+= Tasks @[tasks]
+
+- [x] write @<ft>{soon}
+  1. draft
+  2. review
+- [ ] publish
+
+```:rust
+fn main() {}
+```
+
+see @<jmp>(tasks)
+"#;
+        let ast = parse(s).unwrap();
+        let mut ctx = Context::new(Backend::Markdown);
+        assert_eq!(
+            markdown::render(&ast, &mut ctx),
+            r#"---
+title: "Notes"
+tags: ["a", "b"]
+---
+
+# Tasks<a id="tasks"></a>
+
+- [x] write [^1]
+  1. draft
+  2. review
+- [ ] publish
+
+```rust
+fn main() {}
+```
+
+see [1 Tasks](#tasks)
+
+[^1]: soon
+"#
+        );
+    }
+}
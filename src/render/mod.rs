@@ -0,0 +1,593 @@
+pub mod html;
+pub mod latex;
+pub mod markdown;
+pub mod pandoc;
+pub mod review;
+pub mod text;
+
+use std::collections::HashMap;
+
+use crate::ast;
+use crate::diagnostic::Diagnostic;
+use crate::func::{Expansion, FunctionRegistry};
+use crate::token;
+use crate::xref::{self, Target};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Html,
+    Latex,
+    Markdown,
+    Pandoc,
+    Review,
+    Text,
+}
+
+pub type ResolveMention = dyn Fn(&ast::Mention) -> Option<String>;
+
+/// Maps mentions to profile URLs.
+pub enum MentionResolver {
+    /// `https://host/@user`; bare `@user` uses `default_host` if set.
+    Mastodon {
+        default_host: Option<String>,
+    },
+    Custom(Box<ResolveMention>),
+}
+
+impl Default for MentionResolver {
+    fn default() -> Self {
+        MentionResolver::Mastodon { default_host: None }
+    }
+}
+
+impl MentionResolver {
+    pub fn resolve(&self, m: &ast::Mention) -> Option<String> {
+        match self {
+            MentionResolver::Mastodon { default_host } => {
+                let host = m.host.as_ref().or(default_host.as_ref())?;
+                Some(format!("https://{}/@{}", host, m.user))
+            }
+            MentionResolver::Custom(f) => f(m),
+        }
+    }
+}
+
+/// State shared by a single rendering pass.
+pub struct Context<'a> {
+    pub backend: Backend,
+    pub registry: Option<&'a FunctionRegistry>,
+    pub mention: MentionResolver,
+    /// Show comments kept by the parser, e.g. reviewers' notes.
+    pub draft: bool,
+    pub text: text::Options,
+    pub latex: latex::Options,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Bodies of the footnotes referenced so far; footnote `n` is at `n - 1`.
+    pub footnotes: Vec<String>,
+    footnote_defs: HashMap<String, String>,
+    link_defs: HashMap<String, ast::Link>,
+    pub tags: HashMap<String, Target>,
+    /// Numbers of the current section and its parents, `[1, 2]` in 1.2.
+    pub sections: Vec<usize>,
+    /// Number of figures rendered so far.
+    pub figures: usize,
+    /// Number of tables rendered so far.
+    pub tables: usize,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(backend: Backend) -> Self {
+        Self {
+            backend,
+            registry: None,
+            mention: MentionResolver::default(),
+            draft: false,
+            text: text::Options::default(),
+            latex: latex::Options::default(),
+            diagnostics: Vec::new(),
+            footnotes: Vec::new(),
+            footnote_defs: HashMap::new(),
+            link_defs: HashMap::new(),
+            tags: HashMap::new(),
+            sections: Vec::new(),
+            figures: 0,
+            tables: 0,
+        }
+    }
+
+    /// Collect document-wide information before rendering `root`.
+    ///
+    /// Backends call this once from their entry point.
+    pub fn prepare(&mut self, root: &ast::Node) {
+        let (tags, diagnostics) = xref::collect(root);
+        self.tags = tags;
+        self.diagnostics.extend(diagnostics);
+
+        let mut tags = Vec::new();
+        root.walk(&mut |n| {
+            for c in n.inline().into_iter().flatten() {
+                match c {
+                    ast::ParagraphChild::Tag(t) => tags.push(t.as_str()),
+                    ast::ParagraphChild::LinkDef(d) => {
+                        // redefinitions are reported by `link::resolve`
+                        self.link_defs
+                            .entry(d.name.clone())
+                            .or_insert_with(|| d.link.clone());
+                    }
+                    ast::ParagraphChild::Footnote(ast::Footnote { tag: Some(t), body }) => {
+                        if t.is_empty() {
+                            self.diagnostics
+                                .push(Diagnostic::error("`@<ftref>` needs a tag"));
+                        } else if self.footnote_defs.contains_key(t) {
+                            self.diagnostics.push(Diagnostic::error(format!(
+                                "footnote for `@[{}]` is defined more than once",
+                                t
+                            )));
+                        } else {
+                            self.footnote_defs.insert(t.clone(), body.clone());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        let mut dangling: Vec<&String> = self
+            .footnote_defs
+            .keys()
+            .filter(|t| !tags.contains(&t.as_str()))
+            .collect();
+        dangling.sort();
+        for t in dangling {
+            // footnotes are only marked where `@[tag]` is written inline
+            let msg = if self.tags.contains_key(t) {
+                format!(
+                    "footnote refers to `@[{}]`, but the tag is not referenced inline",
+                    t
+                )
+            } else {
+                format!("footnote refers to `@[{}]`, but the tag does not exist", t)
+            };
+            self.diagnostics.push(Diagnostic::error(msg));
+        }
+    }
+
+    /// Number a footnote. Returns its 1-based number.
+    pub fn add_footnote(&mut self, body: &str) -> usize {
+        self.footnotes.push(body.to_string());
+        self.footnotes.len()
+    }
+
+    /// Link text for `@<jmp>(tag)`, or `None` (and a diagnostic) if the tag
+    /// does not exist.
+    pub fn jump_label(&mut self, jump: &ast::Jump) -> Option<String> {
+        match self.tags.get(&jump.tag) {
+            Some(t) => Some(t.label(&jump.tag)),
+            None => {
+                self.diagnostics
+                    .push(xref::unresolved(&jump.tag).with_span(jump.span));
+                None
+            }
+        }
+    }
+
+    /// The footnote attached to `@[tag]`, if any.
+    pub fn footnote_for_tag(&self, tag: &str) -> Option<&str> {
+        self.footnote_defs.get(tag).map(|s| s.as_str())
+    }
+
+    /// The link `@<linkref>` points to, for trees that did not go through
+    /// `link::resolve`.
+    ///
+    /// Returns `None` (and records a diagnostic) if the name is not defined.
+    pub fn link_for_ref(&mut self, r: &ast::LinkRef) -> Option<ast::Link> {
+        match self.link_defs.get(&r.name) {
+            Some(l) => Some(ast::Link {
+                label: r.label.clone(),
+                ..l.clone()
+            }),
+            None => {
+                self.diagnostics.push(
+                    Diagnostic::error(format!("link `{}` is not defined", r.name))
+                        .with_span(r.span),
+                );
+                None
+            }
+        }
+    }
+
+    /// Run the registered handler for `func`.
+    ///
+    /// Without one, the markup functions shared with Re:VIEW (see `Markup`)
+    /// are written in the backend's own syntax. Returns `None` (and records a
+    /// diagnostic) for any other function; backends then fall back to the
+    /// block text.
+    pub fn expand(&mut self, func: &ast::Func) -> Option<Expansion> {
+        let registry = self.registry;
+        if let Some(handler) = registry.and_then(|r| r.get(&func.name)) {
+            return Some(handler(func, self));
+        }
+        if let Some(m) = Markup::of(func) {
+            return Some(m.expand(func, self.backend));
+        }
+        self.diagnostics.push(Diagnostic::warning(format!(
+            "unknown function `@<{}>`",
+            func.name
+        )));
+        None
+    }
+}
+
+/// `@<b>`/`@<strong>`, `@<i>`/`@<em>` and `@<code>`/`@<tt>`, which
+/// documents imported from Re:VIEW use for inline markup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Markup {
+    Strong,
+    Emph,
+    Code,
+}
+
+impl Markup {
+    pub fn of(func: &ast::Func) -> Option<Self> {
+        match func.name.as_str() {
+            "b" | "strong" => Some(Markup::Strong),
+            "i" | "em" => Some(Markup::Emph),
+            "code" | "tt" => Some(Markup::Code),
+            _ => None,
+        }
+    }
+
+    fn expand(self, func: &ast::Func, backend: Backend) -> Expansion {
+        let s = token::unescape_text(func.block.as_deref().unwrap_or_default());
+        let (tag, command, mark) = match self {
+            Markup::Strong => ("strong", "textbf", "**"),
+            Markup::Emph => ("em", "emph", "*"),
+            Markup::Code => ("code", "texttt", "`"),
+        };
+        match backend {
+            Backend::Html => Expansion::Raw(format!("<{0}>{1}</{0}>", tag, html::escape(&s))),
+            Backend::Latex => Expansion::Raw(format!("\\{}{{{}}}", command, latex::escape(&s))),
+            // a code span holding a backquote needs a longer fence
+            Backend::Markdown if self == Markup::Code && s.contains('`') => {
+                Expansion::Raw(format!("`` {} ``", s))
+            }
+            Backend::Markdown if self == Markup::Code => Expansion::Raw(format!("`{}`", s)),
+            Backend::Markdown => Expansion::Raw(format!("{0}{1}{0}", mark, markdown::escape(&s))),
+            _ => Expansion::Nodes(vec![ast::ParagraphChild::Sentence(s)]),
+        }
+    }
+}
+
+/// `0.29` as `29%`, without floating point noise.
+pub(crate) fn percent(w: f64) -> String {
+    let s = format!("{:.2}", w * 100.0);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    format!("{}%", s)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::{
+        html, latex, markdown, pandoc, percent, review, text, Backend, Context, MentionResolver,
+    };
+    use crate::*;
+
+    #[test]
+    fn comment() {
+        let s = "# TODO: cite the paper\nsentence\n";
+        let opts = parse::Options {
+            keep_comments: true,
+            ..Default::default()
+        };
+        let ast = parse_with_options(s, opts).unwrap();
+
+        let mut ctx = Context::new(Backend::Html);
+        assert_eq!(html::render(&ast, &mut ctx), "<p>sentence</p>\n");
+
+        let mut ctx = Context::new(Backend::Html);
+        ctx.draft = true;
+        assert_eq!(
+            html::render(&ast, &mut ctx),
+            "<aside class=\"comment\">TODO: cite the paper</aside>\n<p>sentence</p>\n"
+        );
+
+        let mut ctx = Context::new(Backend::Latex);
+        ctx.draft = true;
+        assert!(latex::render(&ast, &mut ctx)
+            .starts_with("\\marginpar{\\footnotesize TODO: cite the paper}"));
+    }
+
+    #[test]
+    fn ruby() {
+        let s =
+            "@<ruby>(漢字, かんじ)と @<ruby>(振仮名, ふ|り|がな)と @<ruby>(東京|都, とうきょう|と)\n";
+        let ast = parse(s).unwrap();
+
+        let mut ctx = Context::new(Backend::Html);
+        assert_eq!(
+            html::render(&ast, &mut ctx),
+            "<p><ruby>漢字<rt>かんじ</rt></ruby>と \
+             <ruby>振<rt>ふ</rt>仮<rt>り</rt>名<rt>がな</rt></ruby>と \
+             <ruby>東京<rt>とうきょう</rt>都<rt>と</rt></ruby></p>\n"
+        );
+
+        let mut ctx = Context::new(Backend::Latex);
+        let l = latex::render(&ast, &mut ctx);
+        assert!(l.contains("\\ruby{漢字}{かんじ}"));
+        assert!(l.contains("\\ruby{東京|都}{とうきょう|と}"));
+
+        let r = ast::Ruby::new("漢字", "かん|じ");
+        assert_eq!(r.to_string(), "漢字（かんじ）");
+        assert_eq!(format::format(&ast), s);
+
+        // bases and readings are taken as written
+        let ast = parse("@<ruby>(007, ダブルオーセブン)\n").unwrap();
+        let mut ctx = Context::new(Backend::Html);
+        let h = html::render(&ast, &mut ctx);
+        assert!(h.contains("<ruby>007<rt>ダブルオーセブン</rt></ruby>"));
+    }
+
+    #[test]
+    fn markup() {
+        let ast = parse("Re:VIEWは@<b>{太字}や@<i>{a<b}、@<code>{x`y}\n").unwrap();
+
+        let mut ctx = Context::new(Backend::Html);
+        assert_eq!(
+            html::render(&ast, &mut ctx),
+            "<p>Re:VIEWは<strong>太字</strong>や<em>a&lt;b</em>、<code>x`y</code></p>\n"
+        );
+        let mut ctx = Context::new(Backend::Latex);
+        assert!(latex::render(&ast, &mut ctx)
+            .contains("Re:VIEWは\\textbf{太字}や\\emph{a\\textless{}b}、\\texttt{x`y}"));
+        let mut ctx = Context::new(Backend::Markdown);
+        assert!(markdown::render(&ast, &mut ctx).contains("Re:VIEWは**太字**や*a\\<b*、`` x`y ``"));
+        let mut ctx = Context::new(Backend::Text);
+        assert_eq!(text::render(&ast, &mut ctx), "Re:VIEWは太字やa<b、x`y\n\n");
+        assert!(ctx.diagnostics.is_empty(), "{:?}", ctx.diagnostics);
+
+        let mut ctx = Context::new(Backend::Pandoc);
+        let json = pandoc::render(&ast, &mut ctx);
+        let v: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let para = v["blocks"][0]["c"].as_array().unwrap();
+        assert_eq!(para[1]["t"], "Strong");
+        assert_eq!(para[3]["t"], "Emph");
+        assert_eq!(para[5]["t"], "Code");
+        assert_eq!(para[5]["c"][1], "x`y");
+        assert!(ctx.diagnostics.is_empty(), "{:?}", ctx.diagnostics);
+    }
+
+    #[test]
+    fn code() {
+        let ast = parse("run `a<b`\n").unwrap();
+
+        let mut ctx = Context::new(Backend::Html);
+        assert_eq!(
+            html::render(&ast, &mut ctx),
+            "<p>run <code>a&lt;b</code></p>\n"
+        );
+        let mut ctx = Context::new(Backend::Latex);
+        assert!(latex::render(&ast, &mut ctx).contains("run \\texttt{a\\textless{}b}"));
+        let mut ctx = Context::new(Backend::Markdown);
+        assert!(markdown::render(&ast, &mut ctx).contains("run `a<b`"));
+        let mut ctx = Context::new(Backend::Text);
+        assert_eq!(text::render(&ast, &mut ctx), "run a<b\n\n");
+        let mut ctx = Context::new(Backend::Review);
+        assert!(review::render(&ast, &mut ctx).contains("run @<code>{a<b}"));
+        assert_eq!(format::format(&ast), "run `a<b`\n");
+
+        let mut ctx = Context::new(Backend::Pandoc);
+        let json = pandoc::render(&ast, &mut ctx);
+        let v: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let para = v["blocks"][0]["c"].as_array().unwrap();
+        assert_eq!(para[2]["t"], "Code");
+        assert_eq!(para[2]["c"][1], "a<b");
+    }
+
+    #[test]
+    fn autolink() {
+        let ast = parse("email test: sksat@sksat.net, web: https://sksat.net/\n").unwrap();
+
+        let mut ctx = Context::new(Backend::Html);
+        let h = html::render(&ast, &mut ctx);
+        assert_eq!(
+            h,
+            "<p>email test: <a href=\"mailto:sksat@sksat.net\">sksat@sksat.net</a>, \
+             web: <a href=\"https://sksat.net/\">https://sksat.net/</a></p>\n"
+        );
+
+        let mut ctx = Context::new(Backend::Latex);
+        let l = latex::render(&ast, &mut ctx);
+        assert!(l.contains("\\href{mailto:sksat@sksat.net}{sksat@sksat.net}"));
+        assert!(l.contains("\\url{https://sksat.net/}"));
+    }
+
+    #[test]
+    fn link() {
+        let s = "@<link>(https://twitter.com, title=\"Twitter\"){link}, \
+                 @<link>(sksat@sksat.net){mail me}\n";
+        let ast = parse(s).unwrap();
+
+        let mut ctx = Context::new(Backend::Html);
+        let h = html::render(&ast, &mut ctx);
+        assert!(h.contains("<a href=\"https://twitter.com\" title=\"Twitter\">link</a>"));
+        assert!(h.contains("<a href=\"mailto:sksat@sksat.net\">mail me</a>"));
+
+        let mut ctx = Context::new(Backend::Latex);
+        let l = latex::render(&ast, &mut ctx);
+        assert!(l.contains("\\href{https://twitter.com}{link}"));
+
+        // rendered without `link::resolve`
+        let s = "@<linkdef>(rust, https://www.rust-lang.org/)\n\n\
+                 see @<linkref>(rust){Rust} and @<linkref>(nothing){oops}\n";
+        let ast = parse(s).unwrap();
+        let mut ctx = Context::new(Backend::Html);
+        let h = html::render(&ast, &mut ctx);
+        assert_eq!(
+            h,
+            "<p>see <a href=\"https://www.rust-lang.org/\">Rust</a> and oops</p>\n"
+        );
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert!(ctx.diagnostics[0].message.contains("nothing"));
+
+        // names and titles are taken as written
+        let s = "@<linkdef>(01, https://a.example/, title=1.10)\n\n\
+                 @<linkref>(01){a} @<linkref>(1){b}\n";
+        let ast = parse(s).unwrap();
+        let mut ctx = Context::new(Backend::Html);
+        let h = html::render(&ast, &mut ctx);
+        assert!(h.contains("<a href=\"https://a.example/\" title=\"1.10\">a</a> b"));
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert!(ctx.diagnostics[0].message.contains("`1`"));
+    }
+
+    #[test]
+    fn figure() {
+        let s = r#"@<img>(a.png){first}
+
+@[arch]
+
+@<img>(arch.svg, alt="overview", width=0.8){Architecture}
+
+see @<jmp>(arch).
+"#;
+        let ast = parse(s).unwrap();
+
+        let mut ctx = Context::new(Backend::Html);
+        let h = html::render(&ast, &mut ctx);
+        assert!(h.contains(
+            "<figure id=\"arch\">\n<img src=\"arch.svg\" alt=\"overview\" style=\"width: 80%\">\n\
+             <figcaption>Figure 2: Architecture</figcaption>\n</figure>\n"
+        ));
+        assert!(h.contains("<a href=\"#arch\">Figure 2</a>"));
+        assert_eq!(percent(0.29), "29%");
+        assert_eq!(percent(0.125), "12.5%");
+
+        let mut ctx = Context::new(Backend::Latex);
+        let l = latex::render(&ast, &mut ctx);
+        assert!(l.contains(
+            "\\includegraphics[width=0.8\\linewidth]{arch.svg}\n\\caption{Architecture}\n\\label{arch}\n"
+        ));
+        assert!(l.contains("see \\ref{arch}."));
+
+        // paths and alt texts are taken as written
+        let ast = parse("@<img>(2021-04-01, alt=1.10)\n").unwrap();
+        let mut ctx = Context::new(Backend::Html);
+        let h = html::render(&ast, &mut ctx);
+        assert!(h.contains("<img src=\"2021-04-01\" alt=\"1.10\">"), "{}", h);
+    }
+
+    #[test]
+    fn table() {
+        let s = r#"@[cmp]
+
+| name | score |
+|:-----|------:|
+| a    | @<link>(https://a.example){1} |
+| b \| c | 2 |
+
+@<table>(Comparison){
+| x | @<link>(https://b.example){y} |
+}
+
+see @<jmp>(cmp).
+"#;
+        let ast = parse(s).unwrap();
+
+        let mut ctx = Context::new(Backend::Html);
+        let h = html::render(&ast, &mut ctx);
+        assert!(
+            h.contains("<table id=\"cmp\">\n<thead>\n<tr><th style=\"text-align: left\">name</th>")
+        );
+        assert!(
+            h.contains("<td style=\"text-align: right\"><a href=\"https://a.example\">1</a></td>")
+        );
+        assert!(h.contains("<td style=\"text-align: left\">b | c</td>"));
+        assert!(h.contains("<caption>Table 2: Comparison</caption>"));
+        assert!(h.contains("<a href=\"#cmp\">Table 1</a>"));
+
+        let mut ctx = Context::new(Backend::Latex);
+        let l = latex::render(&ast, &mut ctx);
+        assert!(
+            l.contains("\\label{cmp}\n\\begin{tabular}{lr}\n\\hline\nname & score \\\\\n\\hline\n")
+        );
+
+        let mut ctx = Context::new(Backend::Markdown);
+        let m = markdown::render(&ast, &mut ctx);
+        assert!(m.contains(
+            "| name | score |\n| :-- | --: |\n| a | [1](https://a.example) |\n| b \\| c | 2 |\n"
+        ));
+        assert!(m.contains(
+            "*Table 2: Comparison*\n\n|  |  |\n| --- | --- |\n| x | [y](https://b.example) |\n"
+        ));
+        assert!(ctx.diagnostics.is_empty(), "{:?}", ctx.diagnostics);
+    }
+
+    #[test]
+    fn mention() {
+        let ast = parse("SNS test: @sksat_tty @sksat@mstdn.maud.io.\n").unwrap();
+
+        let mut ctx = Context::new(Backend::Html);
+        let h = html::render(&ast, &mut ctx);
+        assert!(h.contains("<span class=\"mention\">@sksat_tty</span>"));
+        assert!(h.contains(
+            "<a class=\"mention\" href=\"https://mstdn.maud.io/@sksat\">@sksat@mstdn.maud.io</a>."
+        ));
+
+        let mut ctx = Context::new(Backend::Html);
+        ctx.mention = MentionResolver::Mastodon {
+            default_host: Some("example.com".to_string()),
+        };
+        let h = html::render(&ast, &mut ctx);
+        assert!(h.contains("href=\"https://example.com/@sksat_tty\""));
+
+        let mut ctx = Context::new(Backend::Html);
+        ctx.mention = MentionResolver::Custom(Box::new(|m| {
+            Some(format!("https://twitter.com/{}", m.user))
+        }));
+        let h = html::render(&ast, &mut ctx);
+        assert!(h.contains("href=\"https://twitter.com/sksat_tty\""));
+
+        // a lone `@` is text
+        let ast = parse("a @ b\n").unwrap();
+        let mut ctx = Context::new(Backend::Text);
+        assert_eq!(text::render(&ast, &mut ctx), "a @ b\n\n");
+    }
+
+    #[test]
+    fn footnote() {
+        let s = r#"脚注だいすき！ @<ft>{そうか？}
+
+タグが打てると，うれしいんじゃ @[footnote]
+
+@<ftref>(footnote){脚注もタグにくっつけられる}
+@<ftref>(nowhere){dangling}
+"#;
+        let ast = parse(s).unwrap();
+
+        let mut ctx = Context::new(Backend::Html);
+        let h = html::render(&ast, &mut ctx);
+        assert!(h.contains("<sup id=\"fnref-1\"><a href=\"#fn-1\">1</a></sup>"));
+        assert!(h.contains("<li id=\"fn-2\">脚注もタグにくっつけられる"));
+        assert!(!h.contains("dangling"));
+        assert_eq!(ctx.footnotes.len(), 2);
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert!(ctx.diagnostics[0].message.contains("nowhere"));
+
+        let mut ctx = Context::new(Backend::Latex);
+        let l = latex::render(&ast, &mut ctx);
+        assert!(l.contains("うれしいんじゃ \\footnote{脚注もタグにくっつけられる}"));
+
+        // the tag is taken as written, not as the number 7
+        let ast = parse("a @[007]\n\n@<ftref>(007){note}\n").unwrap();
+        let mut ctx = Context::new(Backend::Html);
+        html::render(&ast, &mut ctx);
+        assert_eq!(ctx.footnotes, ["note"]);
+        assert!(ctx.diagnostics.is_empty(), "{:?}", ctx.diagnostics);
+
+        let ast = parse("= Title @[n]\n\n@<ftref>(n){on a section}\n").unwrap();
+        let mut ctx = Context::new(Backend::Html);
+        html::render(&ast, &mut ctx);
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert!(ctx.diagnostics[0].message.contains("not referenced inline"));
+    }
+}
@@ -0,0 +1,523 @@
+//! Pandoc's JSON AST (pandoc-types 1.23, Pandoc 3), for `pandoc -f json`.
+//!
+//! - Sections become `Header`s with the tag as id, followed by their
+//!   contents; the header becomes the metadata.
+//! - Lists, tables, figures, code blocks, math, links and footnotes map to
+//!   their Pandoc counterparts. Task list items start with `☒` or `☐`, as
+//!   Pandoc reads them from Markdown.
+//! - Tags become ids: of a `Div` around a tagged paragraph, or of an empty
+//!   `Span` within one. `@<jmp>` is a link to `#tag`.
+//! - Functions become a `Span` with the function name as class, the named
+//!   arguments as attributes and the others as `arg1`, `arg2`, ...; the
+//!   contents are the block, or the expansion if a handler is registered.
+//!   A function alone in a paragraph becomes a `Div`. Without a handler,
+//!   `@<b>`, `@<i>` and `@<code>` (see `Markup`) become `Strong`, `Emph`
+//!   and `Code` instead.
+//! - Ruby becomes a `Span` of class `ruby` with the reading in `rt`.
+//! - Comments are dropped, or kept as a `Div` of class `comment` in draft
+//!   mode.
+
+use crate::ast;
+use crate::func::Expansion;
+use crate::link;
+use crate::render::{percent, Context, Markup};
+use crate::token;
+
+pub fn render(node: &ast::Node, ctx: &mut Context) -> String {
+    ctx.prepare(node);
+
+    let mut meta = Vec::new();
+    node.walk(&mut |n| {
+        if let ast::Node::Header(h) = n {
+            for (k, v) in &h.fields {
+                meta.push(format!("{}:{}", string(k), meta_value(v)));
+            }
+        }
+    });
+    let blocks = render_node(node, ctx);
+    format!(
+        "{{\"pandoc-api-version\":[1,23,1],\"meta\":{{{}}},\"blocks\":{}}}\n",
+        meta.join(","),
+        array(blocks)
+    )
+}
+
+fn render_node(node: &ast::Node, ctx: &mut Context) -> Vec<String> {
+    match node {
+        ast::Node::Section(s) => render_section(s, ctx),
+        ast::Node::Paragraph(p) => render_paragraph(p, ctx),
+        ast::Node::Figure(f) => vec![render_figure(f, ctx)],
+        ast::Node::Table(t) => vec![render_table(t, ctx)],
+        ast::Node::List(l) => vec![render_list(l, ctx)],
+        ast::Node::Comment(c) if ctx.draft => {
+            let para = el("Para", array(text(&c.body)));
+            vec![el(
+                "Div",
+                format!("[{},{}]", attr("", &["comment"], &[]), array(vec![para])),
+            )]
+        }
+        ast::Node::Block(ast::Block::Code(c)) => {
+            let class: Vec<&str> = c.lang.iter().map(|l| l.as_str()).collect();
+            let id = c.tag.as_deref().unwrap_or_default();
+            vec![el(
+                "CodeBlock",
+                format!("[{},{}]", attr(id, &class, &[]), string(&c.src)),
+            )]
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn render_section(s: &ast::Section, ctx: &mut Context) -> Vec<String> {
+    let mut blocks = Vec::new();
+    if s.level > 0 {
+        let id = s.tag.as_deref().unwrap_or_default();
+        blocks.push(el(
+            "Header",
+            format!(
+                "[{},{},{}]",
+                s.level,
+                attr(id, &[], &[]),
+                array(text(&s.title))
+            ),
+        ));
+    }
+    for c in &s.child {
+        blocks.extend(render_node(c, ctx));
+    }
+    blocks
+}
+
+fn render_paragraph(p: &ast::Paragraph, ctx: &mut Context) -> Vec<String> {
+    let block = match p.child.as_slice() {
+        [ast::ParagraphChild::Func(f)] if markup(f, ctx).is_none() => {
+            let (attr, inlines) = render_func(f, ctx);
+            el(
+                "Div",
+                format!("[{},{}]", attr, array(vec![el("Para", inlines)])),
+            )
+        }
+        _ => {
+            let inlines = render_children(&p.child, ctx);
+            if inlines.is_empty() {
+                return Vec::new();
+            }
+            el("Para", array(inlines))
+        }
+    };
+    match &p.tag {
+        Some(t) => vec![el(
+            "Div",
+            format!("[{},{}]", attr(t, &[], &[]), array(vec![block])),
+        )],
+        None => vec![block],
+    }
+}
+
+fn render_figure(f: &ast::Figure, ctx: &mut Context) -> String {
+    ctx.figures += 1;
+
+    let width = match &f.width {
+        Some(token::Literal::Float(w)) => Some(percent(*w)),
+        Some(token::Literal::Int(w)) => Some(format!("{}px", w)),
+        Some(w) => Some(w.to_string()),
+        None => None,
+    };
+    let kv: Vec<(String, String)> = width
+        .into_iter()
+        .map(|w| ("width".to_string(), w))
+        .collect();
+    let alt = f.alt.as_ref().or(f.caption.as_ref());
+    let image = el(
+        "Image",
+        format!(
+            "[{},{},[{},\"\"]]",
+            attr("", &[], &kv),
+            array(alt.map(|a| text(a)).unwrap_or_default()),
+            string(&f.path)
+        ),
+    );
+    el(
+        "Figure",
+        format!(
+            "[{},{},{}]",
+            attr(f.tag.as_deref().unwrap_or_default(), &[], &[]),
+            caption(f.caption.as_deref()),
+            array(vec![el("Plain", array(vec![image]))])
+        ),
+    )
+}
+
+fn caption(c: Option<&str>) -> String {
+    let blocks = match c {
+        Some(c) => vec![el("Plain", array(text(c)))],
+        None => Vec::new(),
+    };
+    format!("[null,{}]", array(blocks))
+}
+
+fn render_table(t: &ast::Table, ctx: &mut Context) -> String {
+    ctx.tables += 1;
+
+    let n = t.columns();
+    let colspecs: Vec<String> = (0..n)
+        .map(|i| format!("[{},{{\"t\":\"ColWidthDefault\"}}]", align(t.align(i))))
+        .collect();
+    let rows = |rows: &[Vec<ast::Cell>], ctx: &mut Context| -> String {
+        let rows: Vec<String> = rows
+            .iter()
+            .map(|r| {
+                let cells: Vec<String> = (0..n)
+                    .map(|i| {
+                        let inlines = match r.get(i) {
+                            Some(c) => render_children(&c.child, ctx),
+                            None => Vec::new(),
+                        };
+                        let blocks = if inlines.is_empty() {
+                            Vec::new()
+                        } else {
+                            vec![el("Plain", array(inlines))]
+                        };
+                        format!(
+                            "[{},{{\"t\":\"AlignDefault\"}},1,1,{}]",
+                            attr("", &[], &[]),
+                            array(blocks)
+                        )
+                    })
+                    .collect();
+                format!("[{},{}]", attr("", &[], &[]), array(cells))
+            })
+            .collect();
+        array(rows)
+    };
+
+    let head = format!("[{},{}]", attr("", &[], &[]), rows(&t.header, ctx));
+    let body = format!("[{},0,[],{}]", attr("", &[], &[]), rows(&t.body, ctx));
+    let foot = format!("[{},[]]", attr("", &[], &[]));
+    el(
+        "Table",
+        format!(
+            "[{},{},{},{},{},{}]",
+            attr(t.tag.as_deref().unwrap_or_default(), &[], &[]),
+            caption(t.caption.as_deref()),
+            array(colspecs),
+            head,
+            array(vec![body]),
+            foot
+        ),
+    )
+}
+
+fn align(a: ast::Align) -> String {
+    let t = match a {
+        ast::Align::Default => "AlignDefault",
+        ast::Align::Left => "AlignLeft",
+        ast::Align::Center => "AlignCenter",
+        ast::Align::Right => "AlignRight",
+    };
+    unit(t)
+}
+
+fn render_list(l: &ast::List, ctx: &mut Context) -> String {
+    let items: Vec<String> = l
+        .items
+        .iter()
+        .map(|i| {
+            let mut inlines = Vec::new();
+            if let Some(done) = i.check {
+                inlines.push(el("Str", string(if done { "☒" } else { "☐" })));
+                inlines.push(unit("Space"));
+            }
+            inlines.extend(render_children(&i.child, ctx));
+            let mut blocks = vec![el("Plain", array(inlines))];
+            if let Some(l) = &i.list {
+                blocks.push(render_list(l, ctx));
+            }
+            array(blocks)
+        })
+        .collect();
+
+    match l.start {
+        Some(start) => el(
+            "OrderedList",
+            format!(
+                "[[{},{{\"t\":\"Decimal\"}},{{\"t\":\"Period\"}}],{}]",
+                start,
+                array(items)
+            ),
+        ),
+        None => el("BulletList", array(items)),
+    }
+}
+
+fn render_children(child: &[ast::ParagraphChild], ctx: &mut Context) -> Vec<String> {
+    let mut inlines = Vec::new();
+    for c in child {
+        inlines.extend(render_child(c, ctx));
+    }
+    inlines
+}
+
+fn render_child(c: &ast::ParagraphChild, ctx: &mut Context) -> Vec<String> {
+    let inline = match c {
+        ast::ParagraphChild::Sentence(s) => return text(s),
+        ast::ParagraphChild::SoftBreak => unit("SoftBreak"),
+        ast::ParagraphChild::Func(f) => match markup(f, ctx) {
+            Some(m) => m,
+            None => {
+                let (attr, inlines) = render_func(f, ctx);
+                el("Span", format!("[{},{}]", attr, inlines))
+            }
+        },
+        ast::ParagraphChild::Tag(t) => match ctx.footnote_for_tag(t) {
+            Some(body) => {
+                let body = body.to_string();
+                ctx.add_footnote(&body);
+                note(&body)
+            }
+            None => el("Span", format!("[{},[]]", attr(t, &[], &[]))),
+        },
+        ast::ParagraphChild::Jump(j) => {
+            let label = ctx.jump_label(j).unwrap_or_else(|| j.tag.clone());
+            link_el(&text(&label), &format!("#{}", j.tag), "")
+        }
+        ast::ParagraphChild::Mention(m) => match ctx.mention.resolve(m) {
+            Some(url) => link_el(&text(&m.to_string()), &url, ""),
+            None => return text(&m.to_string()),
+        },
+        ast::ParagraphChild::Link(l) => {
+            let label = match (l.kind, &l.label) {
+                (link::Link::URL, None) => vec![el("Str", string(&l.url))],
+                _ => text(l.text()),
+            };
+            link_el(&label, &l.href(), l.title.as_deref().unwrap_or_default())
+        }
+        ast::ParagraphChild::LinkDef(_) => return Vec::new(),
+        ast::ParagraphChild::LinkRef(r) => match ctx.link_for_ref(r) {
+            Some(l) => return render_child(&ast::ParagraphChild::Link(l), ctx),
+            None => return text(r.label.as_ref().unwrap_or(&r.name)),
+        },
+        ast::ParagraphChild::Ruby(r) => {
+            let kv = [("rt".to_string(), r.text.concat())];
+            el(
+                "Span",
+                format!(
+                    "[{},{}]",
+                    attr("", &["ruby"], &kv),
+                    array(text(&r.base.concat()))
+                ),
+            )
+        }
+        ast::ParagraphChild::Code(c) => {
+            el("Code", format!("[{},{}]", attr("", &[], &[]), string(c)))
+        }
+        ast::ParagraphChild::Math(m) => {
+            let kind = if m.display {
+                "DisplayMath"
+            } else {
+                "InlineMath"
+            };
+            el("Math", format!("[{},{}]", unit(kind), string(&m.src)))
+        }
+        ast::ParagraphChild::Footnote(ft) => match &ft.tag {
+            Some(_) => return Vec::new(),
+            None => {
+                ctx.add_footnote(&ft.body);
+                note(&ft.body)
+            }
+        },
+    };
+    vec![inline]
+}
+
+// `@<b>`, `@<i>` and `@<code>` without a handler, as Pandoc's own inlines
+fn markup(f: &ast::Func, ctx: &Context) -> Option<String> {
+    if ctx.registry.is_some_and(|r| r.contains(&f.name)) {
+        return None;
+    }
+    let s = token::unescape_text(f.block.as_deref().unwrap_or_default());
+    Some(match Markup::of(f)? {
+        Markup::Strong => el("Strong", array(text(&s))),
+        Markup::Emph => el("Emph", array(text(&s))),
+        Markup::Code => el("Code", format!("[{},{}]", attr("", &[], &[]), string(&s))),
+    })
+}
+
+// the attributes of the `Span` or `Div` and its contents
+fn render_func(f: &ast::Func, ctx: &mut Context) -> (String, String) {
+    let mut kv: Vec<(String, String)> = f
+        .arg
+        .iter()
+        .enumerate()
+        .map(|(i, a)| (format!("arg{}", i + 1), a.to_string()))
+        .collect();
+    kv.extend(f.named_arg.iter().map(|(k, v)| (k.clone(), v.to_string())));
+
+    // without a handler the `Span` is the whole mapping, not a fallback
+    let handled = ctx.registry.is_some_and(|r| r.contains(&f.name));
+    let inlines = match handled.then(|| ctx.expand(f)).flatten() {
+        Some(Expansion::Nodes(n)) => render_children(&n, ctx),
+        Some(Expansion::Raw(s)) => text(&s),
+        None => f
+            .block
+            .as_deref()
+            .map(|b| text(&token::unescape_text(b)))
+            .unwrap_or_default(),
+    };
+    (attr("", &[f.name.as_str()], &kv), array(inlines))
+}
+
+fn note(body: &str) -> String {
+    el("Note", array(vec![el("Para", array(text(body)))]))
+}
+
+fn link_el(label: &[String], url: &str, title: &str) -> String {
+    el(
+        "Link",
+        format!(
+            "[{},{},[{},{}]]",
+            attr("", &[], &[]),
+            array(label.to_vec()),
+            string(url),
+            string(title)
+        ),
+    )
+}
+
+fn meta_value(v: &token::Literal) -> String {
+    match v {
+        token::Literal::Str(s) => el("MetaInlines", array(text(s))),
+        token::Literal::Bool(b) => el("MetaBool", b.to_string()),
+        token::Literal::List(l) => el("MetaList", array(l.iter().map(meta_value).collect())),
+        _ => el("MetaString", string(&v.to_string())),
+    }
+}
+
+// `Str`s separated by `Space`s
+fn text(s: &str) -> Vec<String> {
+    let mut inlines = Vec::new();
+    let mut word = String::new();
+    for c in s.chars() {
+        if c.is_whitespace() {
+            if !word.is_empty() {
+                inlines.push(el("Str", string(&std::mem::take(&mut word))));
+            }
+            if !matches!(inlines.last(), Some(l) if *l == unit("Space")) {
+                inlines.push(unit("Space"));
+            }
+        } else {
+            word.push(c);
+        }
+    }
+    if !word.is_empty() {
+        inlines.push(el("Str", string(&word)));
+    }
+    inlines
+}
+
+fn attr(id: &str, classes: &[&str], kv: &[(String, String)]) -> String {
+    let classes: Vec<String> = classes.iter().map(|c| string(c)).collect();
+    let kv: Vec<String> = kv
+        .iter()
+        .map(|(k, v)| format!("[{},{}]", string(k), string(v)))
+        .collect();
+    format!("[{},{},{}]", string(id), array(classes), array(kv))
+}
+
+fn el(t: &str, c: String) -> String {
+    format!("{{\"t\":\"{}\",\"c\":{}}}", t, c)
+}
+
+fn unit(t: &str) -> String {
+    format!("{{\"t\":\"{}\"}}", t)
+}
+
+fn array(items: Vec<String>) -> String {
+    format!("[{}]", items.join(","))
+}
+
+/// A JSON string literal.
+pub fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::{pandoc, Backend, Context};
+    use crate::*;
+
+    #[test]
+    fn pandoc() {
+        let s = r#"---
+title: Spec
+draft: true
+---
+
+= Intro @[intro]
+
+See @<jmp>(intro), @<link>(https://example.com){the site} and $x^2$ @<ft>{a note}.
+Press @<kbd>(key=ctrl){C} now.
+
+@<warn>{careful}
+
+- [x] done
+  1. sub
+
+| a | b |
+|:--|--:|
+| 1 | 2 |
+
+@<img>(a.png, width=0.29){cap}
+"#;
+        let ast = parse(s).unwrap();
+        let mut ctx = Context::new(Backend::Pandoc);
+        let json = pandoc::render(&ast, &mut ctx);
+        let v: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(v["pandoc-api-version"][1], 23);
+        assert_eq!(v["meta"]["title"]["t"], "MetaInlines");
+        assert_eq!(v["meta"]["draft"]["c"], true);
+
+        let blocks = &v["blocks"];
+        assert_eq!(blocks[0]["t"], "Header");
+        assert_eq!(blocks[0]["c"][1][0], "intro");
+
+        let para = blocks[1]["c"].as_array().unwrap();
+        assert_eq!(para[2]["t"], "Link");
+        assert_eq!(para[2]["c"][2][0], "#intro");
+        let math = para.iter().find(|i| i["t"] == "Math").unwrap();
+        assert_eq!(math["c"][0]["t"], "InlineMath");
+        assert_eq!(math["c"][1], "x^2");
+        let note = para.iter().find(|i| i["t"] == "Note").unwrap();
+        assert_eq!(note["c"][0]["c"][0]["c"], "a");
+        let span = para.iter().find(|i| i["t"] == "Span").unwrap();
+        assert_eq!(span["c"][0][1][0], "kbd");
+        assert_eq!(span["c"][0][2][0][0], "key");
+        assert_eq!(span["c"][1][0]["c"], "C");
+
+        assert_eq!(blocks[2]["t"], "Div");
+        assert_eq!(blocks[2]["c"][0][1][0], "warn");
+        assert_eq!(blocks[3]["t"], "BulletList");
+        assert_eq!(blocks[3]["c"][0][0]["c"][0]["c"], "☒");
+        assert_eq!(blocks[3]["c"][0][1]["t"], "OrderedList");
+        assert_eq!(blocks[4]["t"], "Table");
+        assert_eq!(blocks[4]["c"][2][1][0]["t"], "AlignRight");
+        let image = &blocks[5]["c"][2][0]["c"][0];
+        assert_eq!(image["c"][0][2][0][1], "29%");
+        assert!(ctx.diagnostics.is_empty(), "{:?}", ctx.diagnostics);
+    }
+}
@@ -0,0 +1,303 @@
+//! Re:VIEW markup, for chapters that move back to a Re:VIEW toolchain.
+//!
+//! - Sections become `=` headings with their tag as `={id}`; the header is
+//!   dropped, as Re:VIEW keeps it in `config.yml`.
+//! - Code blocks become `//list[id][][lang]` when tagged and `//emlist`
+//!   otherwise, figures `//image`, tables `//table` or `//emtable`, and
+//!   display math `//texequation`.
+//! - Footnotes become `@<fn>{id}` with `//footnote[id][...]` after the
+//!   block; the tag is the id, or `fnN` for `@<ft>`.
+//! - `@<jmp>` becomes `@<hd>`, `@<list>`, `@<img>` or `@<table>`
+//!   depending on the target, and plain text for other targets.
+//! - Functions are written as Re:VIEW inline commands of the same name,
+//!   e.g. `@<b>{bold}`, since the syntax is shared.
+//! - A Re:VIEW image id names `images/<id>.*`; figures use their tag, or
+//!   the file name without its extension.
+
+use std::path::Path;
+
+use crate::ast;
+use crate::diagnostic::Diagnostic;
+use crate::render::Context;
+use crate::token;
+use crate::xref::TargetKind;
+
+pub fn render(node: &ast::Node, ctx: &mut Context) -> String {
+    ctx.prepare(node);
+    render_node(node, ctx)
+}
+
+fn render_node(node: &ast::Node, ctx: &mut Context) -> String {
+    let n = ctx.footnotes.len();
+    let s = match node {
+        ast::Node::Section(s) => return render_section(s, ctx),
+        ast::Node::Paragraph(p) => match p.child.as_slice() {
+            [ast::ParagraphChild::Math(m)] if m.display => {
+                format!("//texequation{{\n{}\n//}}\n\n", m.src)
+            }
+            _ => {
+                let cs = render_children(&p.child, ctx);
+                if cs.is_empty() {
+                    return String::new();
+                }
+                format!("{}\n\n", cs)
+            }
+        },
+        ast::Node::Figure(f) => render_figure(f, ctx),
+        ast::Node::Table(t) => render_table(t, ctx),
+        ast::Node::List(l) => render_list(l, 1, ctx) + "\n",
+        ast::Node::Comment(c) => {
+            let lines: String = c.body.lines().map(|l| format!("#@# {}\n", l)).collect();
+            lines + "\n"
+        }
+        ast::Node::Block(ast::Block::Code(c)) => {
+            let lang = c
+                .lang
+                .as_ref()
+                .map(|l| format!("[{}]", l))
+                .unwrap_or_default();
+            let open = match &c.tag {
+                Some(t) => format!("//list[{}][]{}", t, lang),
+                None if lang.is_empty() => "//emlist".to_string(),
+                None => format!("//emlist[]{}", lang),
+            };
+            format!("{}{{\n{}//}}\n\n", open, c.src)
+        }
+        _ => String::new(),
+    };
+    s + &footnotes(node, n, ctx)
+}
+
+fn render_section(s: &ast::Section, ctx: &mut Context) -> String {
+    let mut cs = String::new();
+    for c in &s.child {
+        cs += &render_node(c, ctx);
+    }
+
+    if s.level == 0 {
+        return cs;
+    }
+    let tag = s
+        .tag
+        .as_ref()
+        .map(|t| format!("{{{}}}", t))
+        .unwrap_or_default();
+    format!("{}{} {}\n\n{}", "=".repeat(s.level), tag, s.title, cs)
+}
+
+// `//footnote`s for the footnotes marked in `node`, numbered after `n`
+fn footnotes(node: &ast::Node, mut n: usize, ctx: &Context) -> String {
+    let mut s = String::new();
+    for c in node.inline().into_iter().flatten() {
+        let (id, body) = match c {
+            ast::ParagraphChild::Tag(t) => match ctx.footnote_for_tag(t) {
+                Some(body) => (t.clone(), body),
+                None => continue,
+            },
+            ast::ParagraphChild::Footnote(ast::Footnote { tag: None, body }) => {
+                (format!("fn{}", n + 1), body.as_str())
+            }
+            _ => continue,
+        };
+        n += 1;
+        s += &format!("//footnote[{}][{}]\n", id, escape_arg(body));
+    }
+    if !s.is_empty() {
+        s += "\n";
+    }
+    s
+}
+
+fn render_figure(f: &ast::Figure, ctx: &mut Context) -> String {
+    ctx.figures += 1;
+
+    let stem = Path::new(&f.path)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let id = match &f.tag {
+        Some(t) => {
+            if *t != stem {
+                ctx.diagnostics.push(
+                    Diagnostic::warning(format!(
+                        "Re:VIEW looks for `{}` as `images/{}.*`",
+                        f.path, t
+                    ))
+                    .with_span(f.span),
+                );
+            }
+            t.clone()
+        }
+        None => stem,
+    };
+    let caption = f.caption.as_deref().unwrap_or_default();
+    let mut s = format!("//image[{}][{}]", id, escape_arg(caption));
+    if let Some(token::Literal::Float(w)) = &f.width {
+        s += &format!("[scale={}]", w);
+    }
+    s + "\n\n"
+}
+
+fn render_table(t: &ast::Table, ctx: &mut Context) -> String {
+    ctx.tables += 1;
+
+    let caption = t.caption.as_deref().map(escape_arg);
+    let mut s = match (&t.tag, caption) {
+        (Some(tag), c) => format!("//table[{}][{}]{{\n", tag, c.unwrap_or_default()),
+        (None, Some(c)) => format!("//emtable[{}]{{\n", c),
+        (None, None) => "//emtable{\n".to_string(),
+    };
+    let row = |r: &[ast::Cell], ctx: &mut Context| {
+        let cells: Vec<String> = r
+            .iter()
+            .map(|c| {
+                let c = render_children(&c.child, ctx).replace(['\t', '\n'], " ");
+                // `.` is an empty cell, so a leading one is doubled
+                if c.is_empty() || c.starts_with('.') {
+                    format!(".{}", c)
+                } else {
+                    c
+                }
+            })
+            .collect();
+        cells.join("\t") + "\n"
+    };
+    for r in &t.header {
+        s += &row(r, ctx);
+    }
+    if !t.header.is_empty() {
+        s += "------------\n";
+    }
+    for r in &t.body {
+        s += &row(r, ctx);
+    }
+    s += "//}\n\n";
+    s
+}
+
+// Re:VIEW cannot nest numbered lists, so nested items become bullets
+fn render_list(l: &ast::List, depth: usize, ctx: &mut Context) -> String {
+    let mut s = String::new();
+    for (n, i) in l.items.iter().enumerate() {
+        let marker = match l.start {
+            Some(start) if depth == 1 => format!(" {}. ", start + n),
+            _ => format!(" {} ", "*".repeat(depth)),
+        };
+        let check = match i.check {
+            Some(true) => "[x] ",
+            Some(false) => "[ ] ",
+            None => "",
+        };
+        let text = render_children(&i.child, ctx).replace('\n', "\n  ");
+        s += &format!("{}{}{}\n", marker, check, text);
+        if let Some(l) = &i.list {
+            s += &render_list(l, depth + 1, ctx);
+        }
+    }
+    s
+}
+
+fn render_children(child: &[ast::ParagraphChild], ctx: &mut Context) -> String {
+    let mut cs = String::new();
+    for c in child {
+        cs += &render_child(c, ctx);
+    }
+    cs
+}
+
+fn render_child(c: &ast::ParagraphChild, ctx: &mut Context) -> String {
+    match c {
+        ast::ParagraphChild::Sentence(s) => s.clone(),
+        ast::ParagraphChild::Func(f) => {
+            let content = match (&f.block, f.get_arg(0)) {
+                (Some(b), _) => token::unescape_text(b),
+                (None, Some(a)) => a.to_string(),
+                (None, None) => String::new(),
+            };
+            format!("@<{}>{{{}}}", f.name, escape(&content))
+        }
+        ast::ParagraphChild::Tag(t) => match ctx.footnote_for_tag(t) {
+            Some(body) => {
+                let body = body.to_string();
+                ctx.add_footnote(&body);
+                format!("@<fn>{{{}}}", t)
+            }
+            None => String::new(),
+        },
+        ast::ParagraphChild::Jump(j) => {
+            let kind = ctx.tags.get(&j.tag).map(|t| t.kind);
+            let cmd = match kind {
+                Some(TargetKind::Section) => "hd",
+                Some(TargetKind::Code) => "list",
+                Some(TargetKind::Figure) => "img",
+                Some(TargetKind::Table) => "table",
+                _ => return ctx.jump_label(j).unwrap_or_else(|| j.tag.clone()),
+            };
+            format!("@<{}>{{{}}}", cmd, escape(&j.tag))
+        }
+        ast::ParagraphChild::Mention(m) => m.to_string(),
+        ast::ParagraphChild::Link(l) => match &l.label {
+            Some(label) => format!("@<href>{{{}, {}}}", escape(&l.href()), escape(label)),
+            None => format!("@<href>{{{}}}", escape(&l.href())),
+        },
+        ast::ParagraphChild::SoftBreak => "\n".to_string(),
+        ast::ParagraphChild::LinkDef(_) => String::new(),
+        ast::ParagraphChild::LinkRef(r) => match ctx.link_for_ref(r) {
+            Some(l) => render_child(&ast::ParagraphChild::Link(l), ctx),
+            None => r.label.as_ref().unwrap_or(&r.name).clone(),
+        },
+        ast::ParagraphChild::Ruby(r) => format!(
+            "@<ruby>{{{}, {}}}",
+            escape(&r.base.concat()),
+            escape(&r.text.concat())
+        ),
+        ast::ParagraphChild::Math(m) if m.display => {
+            format!("@<m>{{\\displaystyle {}}}", escape(&m.src))
+        }
+        ast::ParagraphChild::Math(m) => format!("@<m>{{{}}}", escape(&m.src)),
+        ast::ParagraphChild::Code(c) => format!("@<code>{{{}}}", escape(c)),
+        ast::ParagraphChild::Footnote(ft) => match &ft.tag {
+            Some(_) => String::new(),
+            None => format!("@<fn>{{fn{}}}", ctx.add_footnote(&ft.body)),
+        },
+    }
+}
+
+/// Escape the contents of an inline command, `@<name>{...}`.
+pub fn escape(s: &str) -> String {
+    s.replace('}', "\\}")
+}
+
+// a block command argument, `//name[...]`
+fn escape_arg(s: &str) -> String {
+    s.replace(']', "\\]")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::{review, Backend, Context};
+    use crate::*;
+
+    #[test]
+    fn review() {
+        let s = r#"= Intro @[intro]
+
+See @<jmp>(intro) and @<link>(https://example.com){the site} @<ft>{a note}.
+@<ruby>(漢字, かんじ) $x^2$
+
+1. one
+  1. sub
+2. two
+"#;
+        let ast = parse(s).unwrap();
+        let mut ctx = Context::new(Backend::Review);
+        assert_eq!(
+            review::render(&ast, &mut ctx),
+            "={intro} Intro\n\n\
+             See @<hd>{intro} and @<href>{https://example.com, the site} @<fn>{fn1}. \
+             @<ruby>{漢字, かんじ} @<m>{x^2}\n\n\
+             //footnote[fn1][a note]\n\n\
+             \x201. one\n\x20** sub\n\x202. two\n\n"
+        );
+    }
+}
@@ -0,0 +1,440 @@
+use crate::ast;
+use crate::func::Expansion;
+use crate::linebreak;
+use crate::render::Context;
+use crate::token;
+
+/// Options of the plain-text backend.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Line width in columns; East Asian wide characters take two.
+    pub width: usize,
+    pub punctuation: Punctuation,
+    /// Number sections like `1.2 Title`, as `@<jmp>` labels them.
+    pub numbering: bool,
+    /// Colours and emphasis with ANSI escape sequences, for a terminal.
+    pub ansi: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            width: 80,
+            punctuation: Punctuation::Keep,
+            numbering: false,
+            ansi: false,
+        }
+    }
+}
+
+/// Normalization of ASCII punctuation and its fullwidth forms
+/// (`!` and `！`, `(` and `（`, ...).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Punctuation {
+    Keep,
+    /// Fullwidth next to East Asian characters, e.g. `日本(にほん)` becomes
+    /// `日本（にほん）`.
+    FullWidth,
+    /// Always ASCII.
+    HalfWidth,
+}
+
+/// Render as plain text wrapped to `ctx.text.width`, for email, search
+/// indexing or, with `Options::ansi`, a terminal.
+///
+/// Code blocks are drawn in a box and footnotes are listed at the end.
+pub fn render(node: &ast::Node, ctx: &mut Context) -> String {
+    ctx.prepare(node);
+    let mut s = render_node(node, ctx);
+    s += &render_footnotes(ctx);
+    s
+}
+
+fn render_node(node: &ast::Node, ctx: &mut Context) -> String {
+    match node {
+        ast::Node::Header(_) => String::new(),
+        ast::Node::Section(s) => render_section(s, ctx),
+        ast::Node::Paragraph(p) => {
+            let cs = render_children(&p.child, ctx);
+            if cs.trim().is_empty() {
+                return String::new();
+            }
+            fill(&cs, "", ctx) + "\n"
+        }
+        ast::Node::Figure(f) => {
+            ctx.figures += 1;
+            let s = match &f.caption {
+                Some(c) => format!("[Figure {}: {}] ({})", ctx.figures, c, f.path),
+                None => format!("[Figure {}] ({})", ctx.figures, f.path),
+            };
+            style_lines(&fill(&s, "", ctx), "3", ctx) + "\n"
+        }
+        ast::Node::Table(t) => render_table(t, ctx),
+        ast::Node::List(l) => render_list(l, "", 0, ctx) + "\n",
+        ast::Node::Block(ast::Block::Code(c)) => render_code(c, ctx),
+        ast::Node::Comment(c) if ctx.draft => {
+            style_lines(&fill(&c.body, "| ", ctx), "2", ctx) + "\n"
+        }
+        _ => String::new(),
+    }
+}
+
+fn render_section(s: &ast::Section, ctx: &mut Context) -> String {
+    let mut title = String::new();
+    if s.level > 0 {
+        ctx.sections.resize(s.level, 0);
+        ctx.sections[s.level - 1] += 1;
+        if ctx.text.numbering {
+            let number: Vec<String> = ctx.sections.iter().map(|n| n.to_string()).collect();
+            title = format!("{} ", number.join("."));
+        }
+        title += &s.title;
+    }
+
+    let mut cs = String::new();
+    for c in &s.child {
+        cs += &render_node(c, ctx);
+    }
+
+    if s.level == 0 {
+        return cs;
+    }
+    let (rule, sgr) = if s.level == 1 {
+        ("=", "1;36")
+    } else {
+        ("-", "1")
+    };
+    format!(
+        "{}\n{}\n\n{}",
+        style(&title, sgr, ctx),
+        style(&rule.repeat(linebreak::width(&title)), "2", ctx),
+        cs
+    )
+}
+
+// a box around the code, with the language at the top
+fn render_code(c: &ast::Code, ctx: &Context) -> String {
+    let lines: Vec<String> = c.src.lines().map(|l| l.replace('\t', "    ")).collect();
+    let lang = c
+        .lang
+        .as_ref()
+        .map(|l| format!(" {} ", l))
+        .unwrap_or_default();
+    let inner = lines
+        .iter()
+        .map(|l| linebreak::width(l))
+        .max()
+        .unwrap_or(0)
+        .max(linebreak::width(&lang));
+
+    let top = format!(
+        "┌─{}{}┐",
+        lang,
+        "─".repeat(inner + 1 - linebreak::width(&lang))
+    );
+    let mut s = style(&top, "2", ctx) + "\n";
+    for l in &lines {
+        let pad = " ".repeat(inner - linebreak::width(l));
+        s += &format!(
+            "{} {}{} {}\n",
+            style("│", "2", ctx),
+            l,
+            pad,
+            style("│", "2", ctx)
+        );
+    }
+    let bottom = format!("└{}┘", "─".repeat(inner + 2));
+    s + &style(&bottom, "2", ctx) + "\n\n"
+}
+
+fn render_list(l: &ast::List, indent: &str, depth: usize, ctx: &mut Context) -> String {
+    let mut s = String::new();
+    for (n, i) in l.items.iter().enumerate() {
+        let marker = match l.start {
+            Some(start) => format!("{}. ", start + n),
+            None if ctx.text.ansi => format!("{} ", ["•", "◦", "▪"][depth % 3]),
+            None => "- ".to_string(),
+        };
+        let check = match i.check {
+            Some(true) => "[x] ",
+            Some(false) => "[ ] ",
+            None => "",
+        };
+        let inner = format!("{}{}", indent, " ".repeat(linebreak::width(&marker)));
+        let text = format!("{}{}", check, render_children(&i.child, ctx));
+        for (j, l) in fill(&text, &inner, ctx).lines().enumerate() {
+            if j == 0 {
+                s += indent;
+                s += &marker;
+                s += &l[inner.len()..];
+            } else {
+                s += l;
+            }
+            s += "\n";
+        }
+        if let Some(l) = &i.list {
+            s += &render_list(l, &inner, depth + 1, ctx);
+        }
+    }
+    s
+}
+
+fn render_table(t: &ast::Table, ctx: &mut Context) -> String {
+    ctx.tables += 1;
+
+    let mut s = String::new();
+    if let Some(c) = &t.caption {
+        s += &format!("Table {}: {}\n", ctx.tables, c);
+    }
+
+    let n = t.columns();
+    let mut rows = Vec::new();
+    for (j, r) in t.header.iter().chain(t.body.iter()).enumerate() {
+        let sgr = if j < t.header.len() { "1" } else { "" };
+        let cells: Vec<String> = (0..n)
+            .map(|i| match r.get(i) {
+                Some(c) => style(&render_children(&c.child, ctx), sgr, ctx),
+                None => String::new(),
+            })
+            .collect();
+        rows.push(cells);
+    }
+    let widths: Vec<usize> = (0..n)
+        .map(|i| {
+            rows.iter()
+                .map(|r| linebreak::width(&r[i]))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    for (j, r) in rows.iter().enumerate() {
+        let cells: Vec<String> = r
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let pad = widths[i] - linebreak::width(c);
+                match t.align(i) {
+                    ast::Align::Right => format!("{}{}", " ".repeat(pad), c),
+                    ast::Align::Center => {
+                        format!("{}{}{}", " ".repeat(pad / 2), c, " ".repeat(pad - pad / 2))
+                    }
+                    _ => format!("{}{}", c, " ".repeat(pad)),
+                }
+            })
+            .collect();
+        s += cells.join("  ").trim_end();
+        s += "\n";
+        if j + 1 == t.header.len() {
+            let rule: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+            s += &style(&rule.join("  "), "2", ctx);
+            s += "\n";
+        }
+    }
+    s + "\n"
+}
+
+// wrap and indent every line with `prefix`
+fn fill(s: &str, prefix: &str, ctx: &Context) -> String {
+    let width = ctx.text.width.saturating_sub(linebreak::width(prefix));
+    let mut out = String::new();
+    for l in linebreak::wrap(s, width) {
+        out += prefix;
+        out += &l;
+        out += "\n";
+    }
+    out
+}
+
+fn render_children(child: &[ast::ParagraphChild], ctx: &mut Context) -> String {
+    let mut cs = String::new();
+    for c in child {
+        cs += &render_child(c, ctx);
+    }
+    cs
+}
+
+fn render_child(c: &ast::ParagraphChild, ctx: &mut Context) -> String {
+    match c {
+        ast::ParagraphChild::Sentence(s) => normalize(s, ctx.text.punctuation),
+        ast::ParagraphChild::SoftBreak => " ".to_string(),
+        ast::ParagraphChild::Func(f) => match ctx.expand(f) {
+            Some(Expansion::Nodes(n)) => render_children(&n, ctx),
+            Some(Expansion::Raw(s)) => s,
+            None => f
+                .block
+                .as_deref()
+                .map(token::unescape_text)
+                .unwrap_or_default(),
+        },
+        ast::ParagraphChild::Tag(t) => match ctx.footnote_for_tag(t) {
+            Some(body) => {
+                let body = body.to_string();
+                let mark = format!("[{}]", ctx.add_footnote(&body));
+                style(&mark, "36", ctx)
+            }
+            None => String::new(),
+        },
+        ast::ParagraphChild::Jump(j) => {
+            let label = ctx.jump_label(j).unwrap_or_else(|| j.tag.clone());
+            style(&label, "4", ctx)
+        }
+        ast::ParagraphChild::Mention(m) => style(&m.to_string(), "36", ctx),
+        ast::ParagraphChild::Link(l) => match &l.label {
+            Some(label) => format!("{} <{}>", label, style(&l.url, "4;34", ctx)),
+            None => style(&l.url, "4;34", ctx),
+        },
+        ast::ParagraphChild::LinkDef(_) => String::new(),
+        ast::ParagraphChild::LinkRef(r) => match ctx.link_for_ref(r) {
+            Some(l) => render_child(&ast::ParagraphChild::Link(l), ctx),
+            None => r.label.clone().unwrap_or_else(|| r.name.clone()),
+        },
+        ast::ParagraphChild::Ruby(r) => r.to_string(),
+        ast::ParagraphChild::Math(m) => style(&m.to_string(), "33", ctx),
+        ast::ParagraphChild::Code(c) => c.clone(),
+        ast::ParagraphChild::Footnote(ft) => match &ft.tag {
+            Some(_) => String::new(),
+            None => {
+                let mark = format!("[{}]", ctx.add_footnote(&ft.body));
+                style(&mark, "36", ctx)
+            }
+        },
+    }
+}
+
+// wrap `s` in an ANSI `SGR` sequence, e.g. `1` for bold, with `Options::ansi`
+fn style(s: &str, sgr: &str, ctx: &Context) -> String {
+    if ctx.text.ansi && !sgr.is_empty() && !s.is_empty() {
+        format!("\x1b[{}m{}\x1b[0m", sgr, s)
+    } else {
+        s.to_string()
+    }
+}
+
+fn style_lines(s: &str, sgr: &str, ctx: &Context) -> String {
+    s.lines().map(|l| style(l, sgr, ctx) + "\n").collect()
+}
+
+fn render_footnotes(ctx: &Context) -> String {
+    let mut s = String::new();
+    for (i, body) in ctx.footnotes.iter().enumerate() {
+        let mark = format!("[{}] ", i + 1);
+        let indent = " ".repeat(mark.len());
+        let body = normalize(body, ctx.text.punctuation);
+        // lines of a multi-line body are wrapped separately
+        let body: String = body.lines().map(|l| fill(l, &indent, ctx)).collect();
+        for (j, l) in body.lines().enumerate() {
+            s += &if j == 0 {
+                style(&mark, "36", ctx)
+            } else {
+                indent.clone()
+            };
+            s += &l[indent.len()..];
+            s += "\n";
+        }
+    }
+    s
+}
+
+/// Convert punctuation between ASCII and fullwidth forms.
+pub fn normalize(s: &str, punctuation: Punctuation) -> String {
+    // `!`..`~` and `！`..`～`
+    let fullwidth = |c: char| char::from_u32(c as u32 + 0xFEE0).unwrap();
+    let halfwidth = |c: char| char::from_u32(c as u32 - 0xFEE0).unwrap();
+
+    let chars: Vec<char> = s.chars().collect();
+    let mut out = String::with_capacity(s.len());
+    for (i, &c) in chars.iter().enumerate() {
+        let c = match punctuation {
+            Punctuation::Keep => c,
+            Punctuation::FullWidth if c.is_ascii_punctuation() => {
+                let wide = |c: Option<&char>| c.is_some_and(|c| linebreak::is_wide(*c));
+                let prev = i.checked_sub(1).and_then(|i| chars.get(i));
+                if wide(prev) || wide(chars.get(i + 1)) {
+                    fullwidth(c)
+                } else {
+                    c
+                }
+            }
+            Punctuation::HalfWidth if ('！'..='～').contains(&c) => {
+                let h = halfwidth(c);
+                if h.is_ascii_punctuation() {
+                    h
+                } else {
+                    c
+                }
+            }
+            _ => c,
+        };
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::{text, Backend, Context};
+    use crate::*;
+
+    #[test]
+    fn text() {
+        let s = "= 見出し\n\n吾輩は猫である。名前はまだ無い。どこで生れたかとんと見当がつかぬ(猫) @<ft>{夏目漱石}。\n\n| a | b |\n|---|--:|\n| 猫 | 1 |\n";
+        let ast = parse(s).unwrap();
+
+        let mut ctx = Context::new(Backend::Text);
+        ctx.text.width = 20;
+        ctx.text.punctuation = text::Punctuation::FullWidth;
+        assert_eq!(
+            text::render(&ast, &mut ctx),
+            "見出し\n======\n\n\
+             吾輩は猫である。名前\n\
+             はまだ無い。どこで生\n\
+             れたかとんと見当がつ\n\
+             かぬ（猫） [1]。\n\n\
+             a   b\n\
+             --  -\n\
+             猫  1\n\n\
+             [1] 夏目漱石\n"
+        );
+
+        assert_eq!(
+            text::normalize("（a）！", text::Punctuation::HalfWidth),
+            "(a)!"
+        );
+
+        let ast = parse("a @<ft>{first\nsecond}\n").unwrap();
+        let mut ctx = Context::new(Backend::Text);
+        assert_eq!(
+            text::render(&ast, &mut ctx),
+            "a [1]\n\n[1] first\n    second\n"
+        );
+    }
+
+    #[test]
+    fn terminal() {
+        let s = "= Intro\n\n== Usage @[usage]\n\nSee @<jmp>(usage) @<ft>{note}.\n\n- a\n  - b\n\n```:sh\nwd cat\n```\n";
+        let ast = parse(s).unwrap();
+
+        let mut ctx = Context::new(Backend::Text);
+        ctx.text.numbering = true;
+        assert_eq!(
+            text::render(&ast, &mut ctx),
+            "1 Intro\n=======\n\n\
+             1.1 Usage\n---------\n\n\
+             See 1.1 Usage [1].\n\n\
+             - a\n  - b\n\n\
+             ┌─ sh ───┐\n\
+             │ wd cat │\n\
+             └────────┘\n\n\
+             [1] note\n"
+        );
+
+        let mut ctx = Context::new(Backend::Text);
+        ctx.text.ansi = true;
+        let t = text::render(&ast, &mut ctx);
+        assert!(t.starts_with("\x1b[1;36mIntro\x1b[0m\n\x1b[2m=====\x1b[0m\n"));
+        assert!(t.contains("See \x1b[4m1.1 Usage\x1b[0m \x1b[36m[1]\x1b[0m.\n"));
+        assert!(t.contains("• a\n  ◦ b\n"));
+        assert!(t.contains("\x1b[2m│\x1b[0m wd cat \x1b[2m│\x1b[0m\n"));
+    }
+}
@@ -1,6 +1,9 @@
 use std::ops::Fn;
 
+use crate::parse::ParseError;
+
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Token {
     pub kind: TokenKind,
     pos: usize,
@@ -11,18 +14,268 @@ impl Token {
     pub fn get_str<'a>(&self, src: &'a str) -> &'a str {
         &src[self.pos..self.pos + self.len]
     }
+
+    pub fn span(&self) -> Span {
+        Span {
+            start: self.pos,
+            end: self.pos + self.len,
+        }
+    }
+}
+
+/// Byte range in the source.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// 1-based line and column (in chars) of the start.
+    pub fn line_col(&self, src: &str) -> (usize, usize) {
+        let before = &src[..self.start.min(src.len())];
+        let line = before.matches('\n').count() + 1;
+        let col = match before.rfind('\n') {
+            Some(i) => before[i + 1..].chars().count() + 1,
+            None => before.chars().count() + 1,
+        };
+        (line, col)
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "type", content = "data", rename_all = "snake_case")
+)]
 pub enum Literal {
     Str(String),
     Int(i64),
     Float(f64),
+    Bool(bool),
+    Date(Date),
+    List(Vec<Literal>),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Date {
+    pub year: i32,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl Literal {
+    /// Parse a literal from its source representation.
+    ///
+    /// `"quoted"` is always a string, `[a, b]` is a list, and otherwise
+    /// `true`/`false`, integers, floats and `YYYY-MM-DD` dates are tried in
+    /// turn. Anything else is kept as a string.
+    pub fn parse(s: &str) -> Literal {
+        let s = s.trim();
+
+        if let Some(q) = s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+            return Literal::Str(unescape(q));
+        }
+        if let Some(l) = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            let list = split_list(l)
+                .into_iter()
+                .filter(|e| !e.trim().is_empty())
+                .map(Literal::parse)
+                .collect();
+            return Literal::List(list);
+        }
+
+        match s {
+            "true" => return Literal::Bool(true),
+            "false" => return Literal::Bool(false),
+            _ => {}
+        }
+        if let Ok(i) = s.parse::<i64>() {
+            return Literal::Int(i);
+        }
+        // reject "inf", "NaN" and friends
+        let numeric = s
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E'));
+        if numeric && s.chars().any(|c| c.is_ascii_digit()) {
+            if let Ok(f) = s.parse::<f64>() {
+                return Literal::Float(f);
+            }
+        }
+        if let Some(d) = Date::parse(s) {
+            return Literal::Date(d);
+        }
+
+        Literal::Str(s.to_string())
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Literal::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Literal::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// Integers are widened, so `width=1` and `width=1.0` read the same.
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Literal::Int(i) => Some(*i as f64),
+            Literal::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Literal::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Literal {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Literal::Str(s) => write!(f, "{}", s),
+            Literal::Int(i) => write!(f, "{}", i),
+            Literal::Float(v) => write!(f, "{}", v),
+            Literal::Bool(b) => write!(f, "{}", b),
+            Literal::Date(d) => write!(f, "{}", d),
+            Literal::List(l) => {
+                write!(f, "[")?;
+                for (i, e) in l.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", e)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+impl Date {
+    /// Parse `YYYY-MM-DD`.
+    pub fn parse(s: &str) -> Option<Date> {
+        let mut it = s.split('-');
+        let (y, m, d) = (it.next()?, it.next()?, it.next()?);
+        if it.next().is_some() || y.len() != 4 || m.len() != 2 || d.len() != 2 {
+            return None;
+        }
+        if !s.chars().all(|c| c.is_ascii_digit() || c == '-') {
+            return None;
+        }
+
+        let year: i32 = y.parse().ok()?;
+        let month: u8 = m.parse().ok()?;
+        let day: u8 = d.parse().ok()?;
+
+        let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+        let days = match month {
+            1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+            4 | 6 | 9 | 11 => 30,
+            2 if leap => 29,
+            2 => 28,
+            _ => return None,
+        };
+        if day == 0 || day > days {
+            return None;
+        }
+
+        Some(Date { year, month, day })
+    }
+}
+
+impl std::fmt::Display for Date {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// Characters that a backslash escapes in text, so that e.g. `\@` or a
+/// line starting with `\=` is not read as syntax.
+pub const ESCAPABLE: &[char] = &['\\', '@', '=', '`', '{', '}', '$', '#', '|'];
+
+/// Resolve the escapes in `ESCAPABLE`; any other backslash is kept as is.
+pub fn unescape_text(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut it = s.chars().peekable();
+    while let Some(c) = it.next() {
+        if c == '\\' {
+            if let Some(&e) = it.peek().filter(|e| ESCAPABLE.contains(e)) {
+                out.push(e);
+                it.next();
+                continue;
+            }
+        }
+        out.push(c);
+    }
+    out
+}
+
+pub(crate) fn unescape(s: &str) -> String {
+    let mut out = String::new();
+    let mut it = s.chars();
+    while let Some(c) = it.next() {
+        if c == '\\' {
+            if let Some(c) = it.next() {
+                out.push(c);
+                continue;
+            }
+        }
+        out.push(c);
+    }
+    out
+}
+
+// split on top-level `,`, keeping quoted strings and nested lists intact
+fn split_list(s: &str) -> Vec<&str> {
+    let mut v = Vec::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            '[' if !quoted => depth += 1,
+            ']' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                v.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    v.push(&s[start..]);
+    v
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "type", content = "data", rename_all = "snake_case")
+)]
 pub enum TokenKind {
-    Comment, // # comment
+    Comment, // # comment, #[ block comment ]#
+    Header,  // ---\nkey: value\n---
     Newline,
     Sentence,
     Indent(usize),
@@ -39,11 +292,14 @@ pub enum TokenKind {
     Math,       // $y = f(x)$
     InlineCode, // `printf("hello");`
     Quote,      // > quote
+    TableRow,   // | cell | cell |
+    ListItem,   // - item, - [x] task, 1. item
     CodeBlock(Option<String>),
     Unknown,
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Title {
     pub level: usize,
     pub name: String,
@@ -56,6 +312,7 @@ pub struct Tokenizer<'a> {
     before: TokenKind,
     now: Option<Token>,
     peeked: Option<Option<Token>>,
+    error: Option<ParseError>,
 }
 
 impl<'a> Iterator for Tokenizer<'a> {
@@ -94,8 +351,18 @@ impl<'a> Tokenizer<'a> {
             before: TokenKind::Newline,
             now: None,
             peeked: None,
+            error: None,
+        }
+    }
+    /// A tokenizer for inline content such as a table cell, which has no
+    /// titles, list items or other tokens of the beginning of a line.
+    pub fn inline(src: &'a str) -> Self {
+        Self {
+            before: TokenKind::Sentence,
+            ..Self::new(src)
         }
     }
+
     pub fn src(&self) -> &str {
         &self.src[self.pos..]
     }
@@ -103,22 +370,48 @@ impl<'a> Tokenizer<'a> {
         &self.src[token.pos..(token.pos + token.len)]
     }
 
+    /// The error that ended the token stream early, if any.
+    pub fn take_error(&mut self) -> Option<ParseError> {
+        self.error.take()
+    }
+
+    /// End the token stream with an error the parser found.
+    pub(crate) fn set_error(&mut self, e: ParseError) {
+        self.error = Some(e);
+        self.peeked = Some(None);
+    }
+
+    // end the token stream at malformed input, blaming `len` bytes at `pos`
+    fn fail(&mut self, message: &str, pos: usize, len: usize) -> Option<Token> {
+        self.error = Some(ParseError {
+            message: message.to_string(),
+            span: Span {
+                start: pos,
+                end: pos + len,
+            },
+        });
+        None
+    }
+
     pub fn now(&self) -> Option<Token> {
         self.now.clone()
     }
 
     pub fn peek(&mut self) -> Option<Token> {
-        return if self.peeked.is_some() {
+        if self.peeked.is_some() {
             self.peeked.clone().unwrap()
         } else {
             let t = self.next_token();
             self.peeked.get_or_insert(t).clone()
-        };
+        }
         //let t = self.next_token();
         //self.peeked.get_or_insert(t).clone()
     }
 
     pub fn next_token(&mut self) -> Option<Token> {
+        if self.error.is_some() {
+            return None;
+        }
         //let mut token = None;
         let token = match self.before {
             TokenKind::Newline => self.get_top_token(),
@@ -128,13 +421,17 @@ impl<'a> Tokenizer<'a> {
             _ => self.get_token(),
         };
 
-        if token.is_none() {
-            return None;
-        }
-        let t = token.unwrap();
+        let t = token?;
 
         assert!(self.pos <= t.pos);
-        assert!(t.len != 0);
+        // only a code block, a function argument or a block may be empty
+        assert!(
+            t.len != 0
+                || matches!(
+                    t.kind,
+                    TokenKind::CodeBlock(_) | TokenKind::FuncArg | TokenKind::FuncBlock
+                )
+        );
         self.pos = t.pos + t.len;
 
         self.before = t.kind.clone();
@@ -185,15 +482,12 @@ impl<'a> Tokenizer<'a> {
     where
         F: Fn(char) -> bool,
     {
-        let src = self.src().chars();
         let mut n = 0;
-        for c in src {
-            if f(c) {
-                n += 1;
-            }
-            {
+        for c in self.src().chars() {
+            if !f(c) {
                 break;
             }
+            n += c.len_utf8();
         }
         self.pos += n;
     }
@@ -203,16 +497,25 @@ impl<'a> Tokenizer<'a> {
     }
 
     pub fn get_top_token(&mut self) -> Option<Token> {
-        if self.src().len() == 0 {
+        if self.src().is_empty() {
             return None;
         }
+        if self.pos == 0 {
+            if let Some(t) = self.get_header() {
+                return Some(t);
+            }
+        }
         let c = &self.src().chars().nth(0).unwrap();
 
+        if matches!(c, ' ' | '\t' | '-' | '0'..='9') {
+            if let Some(t) = self.get_list_item() {
+                return Some(t);
+            }
+        }
+
         match c {
             '=' => {
-                let title = get_title(&self.src());
-                if title.is_some() {
-                    let t = title.unwrap();
+                if let Some(t) = get_title(self.src()) {
                     let level = t.level;
                     let len = t.name.len();
                     let kind = TokenKind::Title(t);
@@ -232,39 +535,132 @@ impl<'a> Tokenizer<'a> {
                 })
             }
             '@' => return self.get_at(),
+            '$' => {
+                if let Some(t) = self.get_math() {
+                    return Some(t);
+                }
+            }
             '`' => return self.get_code(),
-            _ => {
-                let s = get_sentence(self.src());
+            '#' => {
+                let t = self.get_comment();
+                if t.is_some() || self.error.is_some() {
+                    return t;
+                }
+            }
+            '|' => {
+                let len = self.src().find('\n').unwrap_or(self.src().len());
                 return Some(Token {
-                    kind: TokenKind::Sentence,
+                    kind: TokenKind::TableRow,
                     pos: self.pos,
-                    len: s.len(),
+                    len,
                 });
             }
+            _ => {}
         }
 
-        None
+        let s = get_sentence(self.src());
+        Some(Token {
+            kind: TokenKind::Sentence,
+            pos: self.pos,
+            len: s.len(),
+        })
     }
 
-    pub fn get_token(&mut self) -> Option<Token> {
-        match &self.src().chars().nth(0).unwrap() {
-            '\n' => {
+    /// The marker of a list item: indentation, `- ` or `1. `, and `[ ] ` or
+    /// `[x] ` for a task.
+    pub fn get_list_item(&self) -> Option<Token> {
+        let line = self.src().split('\n').next().unwrap();
+        let body = line.trim_start_matches([' ', '\t']);
+
+        let rest = match body.strip_prefix("- ") {
+            Some(r) => r,
+            None => {
+                let d = body.find(|c: char| !c.is_ascii_digit())?;
+                if d == 0 || d > 9 {
+                    return None;
+                }
+                body[d..].strip_prefix(". ")?
+            }
+        };
+        let mut len = line.len() - rest.len();
+        for c in ["[ ]", "[x]", "[X]"] {
+            if rest == c || rest.starts_with(&format!("{} ", c)) {
+                len += std::cmp::min(rest.len(), 4);
+            }
+        }
+
+        Some(Token {
+            kind: TokenKind::ListItem,
+            pos: self.pos,
+            len,
+        })
+    }
+
+    /// `# comment` up to the end of the line, or `#[ comment ]#` which may
+    /// span lines. Only recognized at the beginning of a line; `#` followed
+    /// by anything else (e.g. `#hashtag`) is text.
+    pub fn get_comment(&mut self) -> Option<Token> {
+        let src = &self.src[self.pos..];
+        let len = if let Some(body) = src.strip_prefix("#[") {
+            match body.find("]#") {
+                Some(i) => i + 4,
+                None => return self.fail("no end mark `]#` of comment", self.pos, 2),
+            }
+        } else {
+            match src[1..].chars().next() {
+                None => 1,
+                Some(c) if c.is_whitespace() => src.find('\n').unwrap_or(src.len()),
+                Some(_) => return None,
+            }
+        };
+
+        Some(Token {
+            kind: TokenKind::Comment,
+            pos: self.pos,
+            len,
+        })
+    }
+
+    /// Front matter: a `---` fenced block of `key: value` lines at the very
+    /// beginning of the document. The token includes both fences.
+    pub fn get_header(&mut self) -> Option<Token> {
+        let src = self.src();
+        if !src.starts_with("---\n") {
+            return None;
+        }
+
+        let mut end = 3;
+        for l in src[4..].split_inclusive('\n') {
+            let start = end + 1;
+            end = start + l.len() - 1;
+            if l.strip_suffix('\n').unwrap_or(l) == "---" {
                 return Some(Token {
-                    kind: TokenKind::Newline,
+                    kind: TokenKind::Header,
                     pos: self.pos,
-                    len: 1,
+                    len: start + 3,
                 });
             }
-            '@' => {
-                return self.get_at();
-            }
+        }
+        None
+    }
+
+    pub fn get_token(&mut self) -> Option<Token> {
+        match &self.src().chars().next()? {
+            '\n' => Some(Token {
+                kind: TokenKind::Newline,
+                pos: self.pos,
+                len: 1,
+            }),
+            '@' => self.get_at(),
+            '$' if math_len(self.src()).is_some() => self.get_math(),
+            '`' if is_inline_code(self.src()) => self.get_code(),
             _ => {
                 let s = get_sentence(self.src());
-                return Some(Token {
+                Some(Token {
                     kind: TokenKind::Sentence,
                     pos: self.pos,
                     len: s.len(),
-                });
+                })
             }
         }
     }
@@ -275,20 +671,14 @@ impl<'a> Tokenizer<'a> {
 
         let src = self.src();
         let mut c = src.char_indices();
-        let first = c.next().unwrap().1;
-        match first {
-            '<' | '[' => loop {
-                let c = c.next();
-                if c.is_none() {
-                    return None;
-                }
-                let (i, c) = c.unwrap();
-
-                let (end, kind) = match first {
-                    '[' => (']', TokenKind::Tag),
-                    '<' => ('>', TokenKind::Func),
-                    _ => unreachable!(),
-                };
+        // `@` may be the last character
+        if let Some((_, first @ ('<' | '['))) = c.next() {
+            let (end, kind) = match first {
+                '[' => (']', TokenKind::Tag),
+                '<' => ('>', TokenKind::Func),
+                _ => unreachable!(),
+            };
+            for (i, c) in c {
                 if c == end {
                     return Some(Token {
                         kind,
@@ -296,34 +686,66 @@ impl<'a> Tokenizer<'a> {
                         len: i - 1,
                     });
                 }
-            },
-            _ => {}
+            }
+            // without the end mark, the `@` is just text
         }
 
-        for c in c {
-            let (i, c) = c;
+        // @user or @user@host
+        let mut len = src.len();
+        let mut host = false;
+        for (i, c) in src.char_indices() {
             match c {
                 'a'..='z' | 'A'..='Z' => continue,
                 '0'..='9' | '.' | '_' => continue,
+                '-' if host => continue,
+                '@' if !host && i != 0 => host = true,
                 _ => {
-                    return Some(Token {
-                        kind: TokenKind::AtString,
-                        pos: self.pos,
-                        len: i,
-                    });
+                    len = i;
+                    break;
                 }
             }
         }
-        None
+        // trailing punctuation is not part of the handle
+        let len = src[..len].trim_end_matches(['.', '@']).len();
+
+        if len == 0 {
+            // a lone `@` is just text
+            self.pos -= 1;
+            let pos = self.pos;
+            return Some(Token {
+                kind: TokenKind::Sentence,
+                pos,
+                len: get_sentence(&self.src[pos..]).len(),
+            });
+        }
+
+        Some(Token {
+            kind: TokenKind::AtString,
+            pos: self.pos,
+            len,
+        })
+    }
+
+    /// `$math$` or `$$display math$$`, including the dollars.
+    pub fn get_math(&self) -> Option<Token> {
+        Some(Token {
+            kind: TokenKind::Math,
+            pos: self.pos,
+            len: math_len(self.src())?,
+        })
     }
 
     pub fn get_code(&mut self) -> Option<Token> {
         assert_eq!(self.src().chars().nth(0).unwrap(), '`');
+        let start = self.pos;
         self.skip_one('`');
 
-        let src = self.src();
+        let src = &self.src[self.pos..];
         let mut src = src.char_indices();
-        let f = src.next().unwrap().1;
+        let f = match src.next() {
+            Some((_, f)) => f,
+            None => return self.fail("no end mark of inline code", start, 1),
+        };
         if f != '`' {
             // inline code
             for c in src {
@@ -337,15 +759,17 @@ impl<'a> Tokenizer<'a> {
                 }
             }
 
-            todo!("error: no end mark of inline code");
+            return self.fail("no end mark of inline code", start, 1);
         }
 
-        let c = src.next().unwrap().1;
-        if c != '`' {
-            unimplemented!("code block error");
+        if !matches!(src.next(), Some((_, '`'))) {
+            return self.fail("a code block starts with three backquotes", start, 2);
         }
 
-        let c = src.next().unwrap();
+        let c = match src.next() {
+            Some(c) => c,
+            None => return self.fail("no end mark of code block", start, 3),
+        };
         let mut language = None;
         let mut opt_len = 1; // first newline
         if c.1 == ':' {
@@ -359,11 +783,20 @@ impl<'a> Tokenizer<'a> {
                     break;
                 }
             }
+            if end == 0 {
+                return self.fail("no end mark of code block", start, 3);
+            }
+            // the closing fence is looked for after the language
+            let _ = src.find(|&(i, _)| i == end);
             opt_len += end - p + 1;
             let l = &self.src[self.pos + p..self.pos + end];
             language = Some(l.to_string());
         } else if c.1 != '\n' {
-            unimplemented!("code block error")
+            return self.fail(
+                "a code block starts with a newline or `:lang` after the backquotes",
+                start,
+                3,
+            );
         }
 
         let mut count = 0;
@@ -382,12 +815,12 @@ impl<'a> Tokenizer<'a> {
             }
             count = 0;
         }
-        unreachable!();
+        self.fail("no end mark of code block", start, 3)
     }
 
     pub fn get_func_ext_or_default(&mut self) -> Option<Token> {
         let src = &self.src();
-        return match &src.chars().nth(0).unwrap() {
+        match &src.chars().next()? {
             '(' => Some(Token {
                 kind: TokenKind::FuncArgOpen,
                 pos: self.pos,
@@ -395,12 +828,12 @@ impl<'a> Tokenizer<'a> {
             }),
             '{' => self.get_func_block_or_default(),
             _ => self.get_token(),
-        };
+        }
     }
 
-    pub fn get_func_arg(&self) -> Option<Token> {
-        let src = &self.src();
-        if src.chars().nth(0).unwrap() == ')' {
+    pub fn get_func_arg(&mut self) -> Option<Token> {
+        let src = &self.src[self.pos..];
+        if src.chars().next()? == ')' {
             return Some(Token {
                 kind: TokenKind::FuncArgClose,
                 pos: self.pos,
@@ -408,12 +841,21 @@ impl<'a> Tokenizer<'a> {
             });
         }
 
-        let src = src.chars().enumerate();
-
-        for c in src {
-            let (i, c) = c;
+        // `,` and `)` end the argument unless quoted, escaped or nested
+        let mut depth = 0;
+        let mut quoted = false;
+        let mut escaped = false;
+        for (i, c) in src.char_indices() {
+            if escaped {
+                escaped = false;
+                continue;
+            }
             match c {
-                ',' | ')' => {
+                '\\' => escaped = true,
+                '"' => quoted = !quoted,
+                '(' if !quoted => depth += 1,
+                ')' if !quoted && depth > 0 => depth -= 1,
+                ',' | ')' if !quoted && depth == 0 => {
                     //println!("arg: {}", &self.src()[..i]);
                     return Some(Token {
                         kind: TokenKind::FuncArg,
@@ -424,55 +866,118 @@ impl<'a> Tokenizer<'a> {
                 _ => {}
             }
         }
-        None
+        self.fail("no end mark of function arguments", self.pos, src.len())
     }
 
     pub fn get_func_block_or_default(&mut self) -> Option<Token> {
         let src = &self.src();
-        if src.chars().nth(0).unwrap() != '{' {
+        if src.chars().next()? != '{' {
             return self.get_token();
         }
 
-        let mut n = 0;
-        for c in src.char_indices() {
-            let (i, c) = c;
-            if c == '}' {
-                n = i;
-                break;
+        // up to the matching unescaped `}`; blocks of functions inside,
+        // e.g. `@<link>(url){label}` in a table, nest
+        let mut depth = 0;
+        let mut escaped = false;
+        for (i, c) in src.char_indices().skip(1) {
+            if escaped {
+                escaped = false;
+                continue;
+            }
+            match c {
+                '\\' => escaped = true,
+                '{' => depth += 1,
+                '}' if depth > 0 => depth -= 1,
+                '}' => {
+                    return Some(Token {
+                        kind: TokenKind::FuncBlock,
+                        pos: self.pos + 1,
+                        len: i - 1,
+                    })
+                }
+                _ => {}
             }
         }
-
-        Some(Token {
-            kind: TokenKind::FuncBlock,
-            pos: self.pos + 1,
-            len: n - 1,
-        })
+        self.fail("no end mark `}` of function block", self.pos, 1)
     }
 }
 
+// A function or tag starts anywhere, so that `@<b>{...}` can follow Japanese
+// text directly; a mention only starts after whitespace.
 fn get_sentence(s: &str) -> &str {
-    let it = s.chars().enumerate();
-
     let mut before = 'A';
-    let mut n = 0;
-    for c in it {
-        let (i, c) = c;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        if escaped {
+            // `\@` never starts a function, tag or mention
+            escaped = false;
+            before = 'A';
+            continue;
+        }
 
         match c {
-            '\n' => break,
-            '@' => {
-                if before.is_whitespace() {
-                    break;
-                }
+            '\n' if i != 0 => return &s[..i],
+            '@' if before.is_whitespace() => return &s[..i],
+            '@' if i != 0 && s[i + 1..].starts_with(['<', '[']) => return &s[..i],
+            '$' if i != 0 && math_len(&s[i..]).is_some() => return &s[..i],
+            '`' if i != 0 && is_inline_code(&s[i..]) => return &s[..i],
+            '\\' => escaped = true,
+            _ => {}
+        }
+        before = c;
+    }
+    //println!("sentence: \"{}\"", s);
+    s
+}
+
+/// Whether `s` starts with `` `code` `` closed on the same line.
+///
+/// Within a line a stray backquote stays text; at the beginning of a line
+/// it always starts inline code or a code block.
+fn is_inline_code(s: &str) -> bool {
+    let body = match s.strip_prefix('`') {
+        Some(b) => b.split('\n').next().unwrap_or_default(),
+        None => return false,
+    };
+    !body.starts_with('`') && body.contains('`')
+}
+
+/// Length of the `$math$` or `$$display math$$` at the start of `s`.
+///
+/// As in Pandoc, the opening `$` must be followed and the closing `$`
+/// preceded by a non-space character, and the closing `$` must not be
+/// followed by a digit, so that `$5 and $10` stays text. Inline math ends
+/// at the line; display math may span lines.
+fn math_len(s: &str) -> Option<usize> {
+    if let Some(body) = s.strip_prefix("$$") {
+        let end = body.find("$$")?;
+        if body[..end].trim().is_empty() {
+            return None;
+        }
+        return Some(end + 4);
+    }
+
+    let body = s.strip_prefix('$')?;
+    if body.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let mut escaped = false;
+    let mut before = ' ';
+    for (i, c) in body.char_indices() {
+        match c {
+            '\n' => return None,
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '$' if !before.is_whitespace()
+                && !body[i + 1..].starts_with(|c: char| c.is_ascii_digit()) =>
+            {
+                return Some(i + 2)
             }
             _ => {}
         }
-        n = i;
         before = c;
     }
-    let n = s.char_indices().map(|(i, _)| i).nth(n + 1).unwrap();
-    //println!("sentence: \"{}\"", &s[..n]);
-    &s[..n]
+    None
 }
 
 fn get_title(mut s: &str) -> Option<Title> {
@@ -481,11 +986,7 @@ fn get_title(mut s: &str) -> Option<Title> {
 
     // get level
     loop {
-        let c = it.next();
-        if c.is_none() {
-            return None;
-        }
-        let (_, c) = c.unwrap();
+        let (_, c) = it.next()?;
         match c {
             ' ' => {
                 s = &s[level + 1..];
@@ -496,6 +997,10 @@ fn get_title(mut s: &str) -> Option<Title> {
             _ => return None,
         }
     }
+    // `= ` without a title is text
+    if s.is_empty() {
+        return None;
+    }
 
     Some(Title {
         level,
@@ -570,7 +1075,66 @@ int main(int argc, char **argv){
         let tokenizer = token::Tokenizer::new(s);
         println!("string:\n{}", s);
         for t in tokenizer {
-            println!("{:?}: \"{}\"", t, t.get_str(&s));
+            println!("{:?}: \"{}\"", t, t.get_str(s));
+        }
+    }
+
+    #[test]
+    fn literal() {
+        use token::Literal;
+        assert_eq!(Literal::parse("fig.png"), Literal::Str("fig.png".into()));
+        assert_eq!(Literal::parse(" 42 "), Literal::Int(42));
+        assert_eq!(Literal::parse("0.8"), Literal::Float(0.8));
+        assert_eq!(Literal::parse("true"), Literal::Bool(true));
+        assert_eq!(Literal::parse("\"3\""), Literal::Str("3".into()));
+        assert_eq!(Literal::parse("inf"), Literal::Str("inf".into()));
+        assert_eq!(
+            Literal::parse("2021-02-29"),
+            Literal::Str("2021-02-29".into())
+        );
+        assert_eq!(
+            Literal::parse("2020-02-29"),
+            Literal::Date(token::Date {
+                year: 2020,
+                month: 2,
+                day: 29
+            })
+        );
+        assert_eq!(Literal::parse("[]"), Literal::List(vec![]));
+    }
+
+    #[test]
+    fn comment() {
+        let s = "# note\n#hashtag\n#[ a\nb ]#\n#\n";
+        let t: Vec<_> = token::Tokenizer::new(s)
+            .filter(|t| t.kind != token::TokenKind::Newline)
+            .map(|t| (t.kind.clone(), t.get_str(s)))
+            .collect();
+        assert_eq!(
+            t,
+            vec![
+                (token::TokenKind::Comment, "# note"),
+                (token::TokenKind::Sentence, "#hashtag"),
+                (token::TokenKind::Comment, "#[ a\nb ]#"),
+                (token::TokenKind::Comment, "#"),
+            ]
+        );
+    }
+
+    #[test]
+    fn at() {
+        // a lone `@`, also at the end of the input, is text
+        for s in ["text @", "a\n@", "@", "a @ b"] {
+            let t: Vec<_> = token::Tokenizer::new(s)
+                .filter(|t| t.kind != token::TokenKind::Newline)
+                .map(|t| t.kind)
+                .collect();
+            assert!(
+                t.iter().all(|k| *k == token::TokenKind::Sentence),
+                "{:?}: {:?}",
+                s,
+                t
+            );
         }
     }
 
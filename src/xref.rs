@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+use crate::ast;
+use crate::diagnostic::Diagnostic;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetKind {
+    Section,
+    Paragraph,
+    Code,
+    Figure,
+    Table,
+    /// `@[tag]` in the middle of a paragraph
+    Anchor,
+}
+
+/// Something a `@<jmp>(tag)` can point at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub kind: TargetKind,
+    /// `1.2` for sections, the listing number for code blocks
+    pub number: Option<String>,
+    pub title: Option<String>,
+}
+
+impl Target {
+    /// Text used for a reference to this target.
+    pub fn label(&self, tag: &str) -> String {
+        match (self.kind, &self.number, &self.title) {
+            (TargetKind::Section, Some(n), Some(t)) => format!("{} {}", n, t),
+            (TargetKind::Code, Some(n), _) => format!("List {}", n),
+            (TargetKind::Figure, Some(n), _) => format!("Figure {}", n),
+            (TargetKind::Table, Some(n), _) => format!("Table {}", n),
+            _ => tag.to_string(),
+        }
+    }
+}
+
+/// Collect every tag in the document, reporting duplicates.
+pub fn collect(root: &ast::Node) -> (HashMap<String, Target>, Vec<Diagnostic>) {
+    let mut tags = HashMap::new();
+    let mut diagnostics = Vec::new();
+    let mut add = |tag: &str, target: Target| {
+        if tags.contains_key(tag) {
+            diagnostics.push(Diagnostic::error(format!(
+                "tag `@[{}]` is defined more than once",
+                tag
+            )));
+        } else {
+            tags.insert(tag.to_string(), target);
+        }
+    };
+
+    let mut section: Vec<usize> = Vec::new();
+    let mut code = 0;
+    let mut figure = 0;
+    let mut table = 0;
+    root.walk(&mut |n| {
+        match n {
+            ast::Node::Section(s) if s.level > 0 => {
+                section.resize(s.level, 0);
+                section[s.level - 1] += 1;
+                if let Some(t) = &s.tag {
+                    let number: Vec<String> = section.iter().map(|n| n.to_string()).collect();
+                    add(
+                        t,
+                        Target {
+                            kind: TargetKind::Section,
+                            number: Some(number.join(".")),
+                            title: Some(s.title.clone()),
+                        },
+                    );
+                }
+            }
+            ast::Node::Paragraph(p) => {
+                if let Some(t) = &p.tag {
+                    add(
+                        t,
+                        Target {
+                            kind: TargetKind::Paragraph,
+                            number: None,
+                            title: None,
+                        },
+                    );
+                }
+            }
+            ast::Node::Figure(f) => {
+                // every figure is numbered, tagged or not
+                figure += 1;
+                if let Some(t) = &f.tag {
+                    add(
+                        t,
+                        Target {
+                            kind: TargetKind::Figure,
+                            number: Some(figure.to_string()),
+                            title: f.caption.clone(),
+                        },
+                    );
+                }
+            }
+            ast::Node::Table(tb) => {
+                table += 1;
+                if let Some(t) = &tb.tag {
+                    add(
+                        t,
+                        Target {
+                            kind: TargetKind::Table,
+                            number: Some(table.to_string()),
+                            title: tb.caption.clone(),
+                        },
+                    );
+                }
+            }
+            ast::Node::Block(ast::Block::Code(c)) => {
+                code += 1;
+                if let Some(t) = &c.tag {
+                    add(
+                        t,
+                        Target {
+                            kind: TargetKind::Code,
+                            number: Some(code.to_string()),
+                            title: None,
+                        },
+                    );
+                }
+            }
+            _ => {}
+        }
+
+        for c in n.inline().into_iter().flatten() {
+            if let ast::ParagraphChild::Tag(t) = c {
+                add(
+                    t,
+                    Target {
+                        kind: TargetKind::Anchor,
+                        number: None,
+                        title: None,
+                    },
+                );
+            }
+        }
+    });
+
+    (tags, diagnostics)
+}
+
+/// Report duplicate tags and `@<jmp>` references that resolve to nothing.
+pub fn check(root: &ast::Node) -> Vec<Diagnostic> {
+    let (tags, mut diagnostics) = collect(root);
+    root.walk(&mut |n| {
+        for c in n.inline().into_iter().flatten() {
+            match c {
+                ast::ParagraphChild::Jump(j) if !tags.contains_key(&j.tag) => {
+                    diagnostics.push(unresolved(&j.tag).with_span(j.span));
+                }
+                _ => {}
+            }
+        }
+    });
+    diagnostics
+}
+
+pub(crate) fn unresolved(tag: &str) -> Diagnostic {
+    Diagnostic::error(format!("`@<jmp>({})` refers to an unknown tag", tag))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::xref::TargetKind;
+    use crate::*;
+
+    #[test]
+    fn tags() {
+        let s = r#"= intro @[intro]
+
+== detail @[detail]
+
+```
+untagged
+```
+
+@[hello]
+
+```
+int main(){}
+```
+
+@[para]
+
+see @<jmp>(detail) and @<jmp>(hello), not @<jmp>(none).
+anchor @[intro]
+"#;
+        let ast = parse(s).unwrap();
+        let (tags, diag) = xref::collect(&ast);
+
+        let d = &tags["detail"];
+        assert_eq!(d.kind, TargetKind::Section);
+        assert_eq!(d.label("detail"), "1.1 detail");
+        assert_eq!(tags["hello"].kind, TargetKind::Code);
+        assert_eq!(tags["hello"].label("hello"), "List 2");
+        assert_eq!(tags["para"].kind, TargetKind::Paragraph);
+        assert_eq!(diag.len(), 1);
+        assert!(diag[0].message.contains("intro"));
+
+        let diag = xref::check(&ast);
+        assert_eq!(diag.len(), 2);
+        assert!(diag[1].message.contains("none"));
+
+        // tags that look like numbers are taken as written
+        let ast = parse("= a @[01]\n\n== b @[1.10]\n\nsee @<jmp>(01) and @<jmp>(1.10)\n").unwrap();
+        assert!(xref::check(&ast).is_empty());
+    }
+}